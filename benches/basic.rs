@@ -4,13 +4,13 @@ use std::{
         Arc,
         atomic::{AtomicU8, Ordering},
     },
-    task::Waker,
+    task::{Wake, Waker},
     time::{Duration, Instant},
 };
 
 use criterion::{Criterion, criterion_group, criterion_main};
 use parking_lot::Mutex;
-use sack::WakerSet;
+use sack::{Sack, WakerSet};
 
 trait BenchOps: Default {
     fn add(&self, waker: Waker);
@@ -60,7 +60,7 @@ fn bench_mt<B: BenchOps + Send + Sync>(iters: u64) -> Duration {
             s.spawn(move |_| {
                 for _ in 0..iters {
                     let count = counter.fetch_add(1, Ordering::Relaxed);
-                    if count % 16 == 0 {
+                    if count.is_multiple_of(16) {
                         b.wake();
                     } else {
                         b.add(Waker::noop().clone());
@@ -75,12 +75,94 @@ fn bench_mt<B: BenchOps + Send + Sync>(iters: u64) -> Duration {
     start.elapsed()
 }
 
+struct NoopWaker;
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn bench_repeated(wake_set: &WakerSet, wakers: &[Waker], add: fn(&WakerSet, &Waker)) {
+    for i in 0..16 {
+        add(wake_set, &wakers[i % wakers.len()]);
+    }
+    wake_set.wake_all();
+}
+
+fn bench_sack_drain(sack: &Sack<usize>) {
+    for i in 0..16 {
+        sack.add(i);
+    }
+    sack.drain().for_each(drop);
+}
+
+fn bench_sack_drain_recycle(sack: &Sack<usize>) {
+    for i in 0..16 {
+        sack.add(i);
+    }
+    sack.drain_recycle().for_each(drop);
+}
+
+fn bench_sack_mt(iters: u64, drain: fn(&Sack<usize>)) -> Duration {
+    let sack = Sack::new();
+    let counter = AtomicU8::new(0);
+
+    let start = Instant::now();
+    crossbeam_utils::thread::scope(|s| {
+        for _ in 0..4 {
+            let sack = &sack;
+            let counter = &counter;
+            s.spawn(move |_| {
+                for i in 0..iters {
+                    let count = counter.fetch_add(1, Ordering::Relaxed);
+                    if count.is_multiple_of(16) {
+                        drain(sack);
+                    } else {
+                        sack.add(i as usize);
+                    }
+                }
+            });
+        }
+    })
+    .unwrap();
+
+    start.elapsed()
+}
+
 pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("wake set", |b| b.iter(bench::<WakerSet>));
     c.bench_function("locked vec", |b| b.iter(bench::<LockedVec>));
 
     c.bench_function("wake set mt", |b| b.iter_custom(bench_mt::<WakerSet>));
     c.bench_function("locked vec mt", |b| b.iter_custom(bench_mt::<LockedVec>));
+
+    let wakers: Vec<_> = (0..4).map(|_| Waker::from(Arc::new(NoopWaker))).collect();
+    c.bench_function("wake set repeated", |b| {
+        let wake_set = WakerSet::new();
+        b.iter(|| bench_repeated(&wake_set, &wakers, WakerSet::add_by_ref))
+    });
+    c.bench_function("wake set repeated unique", |b| {
+        let wake_set = WakerSet::new();
+        b.iter(|| {
+            bench_repeated(&wake_set, &wakers, |wake_set, waker| {
+                wake_set.add_unique(waker);
+            })
+        })
+    });
+
+    c.bench_function("sack drain", |b| {
+        let sack = Sack::new();
+        b.iter(|| bench_sack_drain(&sack))
+    });
+    c.bench_function("sack drain recycle", |b| {
+        let sack = Sack::new();
+        b.iter(|| bench_sack_drain_recycle(&sack))
+    });
+
+    c.bench_function("sack drain mt", |b| {
+        b.iter_custom(|iters| bench_sack_mt(iters, |sack| sack.drain().for_each(drop)))
+    });
+    c.bench_function("sack drain recycle mt", |b| {
+        b.iter_custom(|iters| bench_sack_mt(iters, |sack| sack.drain_recycle().for_each(drop)))
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,221 @@
+use core::{
+    future,
+    task::{Context, Poll},
+};
+
+use crate::{
+    Drain, Sack, WakerSet,
+    sync::{
+        atomic::{self, Ordering},
+        const_fn,
+    },
+};
+
+/// A [`Sack<T>`] whose consumers can wait for items asynchronously.
+///
+/// Adding an item wakes every consumer that is waiting in
+/// [`AsyncSack::drain_next_batch`], which makes this a lock-free, unbounded,
+/// multi-producer channel that delivers items in batches.
+///
+/// ## Example
+///
+/// ```
+/// use sack::AsyncSack;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// # fn block_on<F: std::future::Future>(future: F) -> F::Output {
+/// #     use std::task::{Context, Poll, Waker};
+/// #     let mut future = std::pin::pin!(future);
+/// #     let mut cx = Context::from_waker(Waker::noop());
+/// #     loop {
+/// #         if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+/// #             return output;
+/// #         }
+/// #         thread::yield_now();
+/// #     }
+/// # }
+/// let sack = Arc::new(AsyncSack::new());
+///
+/// let producer = {
+///     let sack = Arc::clone(&sack);
+///     thread::spawn(move || sack.add(1))
+/// };
+///
+/// let batch: Vec<_> = block_on(sack.drain_next_batch()).collect();
+/// assert_eq!(batch, vec![1]);
+/// producer.join().unwrap();
+/// ```
+#[derive(Default)]
+pub struct AsyncSack<T> {
+    sack: Sack<T>,
+    consumers: WakerSet,
+}
+
+impl<T> AsyncSack<T> {
+    const_fn! {
+        /// Creates a new, empty sack.
+        pub const fn new() -> Self {
+            Self {
+                sack: Sack::new(),
+                consumers: WakerSet::new(),
+            }
+        }
+    }
+
+    /// Adds an item to the sack and wakes the waiting consumers.
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    pub fn add(&self, item: T) {
+        self.sack.add(item);
+        self.notify();
+    }
+
+    /// Adds all items from an iterator to the sack and wakes the waiting consumers.
+    ///
+    /// See [`Sack::add_all`] for details.
+    pub fn add_all<I: IntoIterator<Item = T>>(&self, items: I) {
+        self.sack.add_all(items);
+        self.notify();
+    }
+
+    /// Wakes the waiting consumers, if there are any.
+    fn notify(&self) {
+        // Pairs with the fence in `poll_drain`, so that either the consumer sees
+        // the new item or we see its waker.
+        atomic::fence(Ordering::SeqCst);
+        if !self.consumers.is_empty() {
+            self.consumers.wake_all();
+        }
+    }
+
+    /// Drains all items from the sack without waiting.
+    ///
+    /// See [`Sack::drain`] for details.
+    pub fn drain(&self) -> Drain<T> {
+        self.sack.drain()
+    }
+
+    /// Waits until the sack isn't empty and drains all items from it.
+    ///
+    /// The returned batch always contains at least one item.
+    pub async fn drain_next_batch(&self) -> Drain<T> {
+        future::poll_fn(|cx| self.poll_drain(cx)).await
+    }
+
+    /// Drains all items from the sack, or registers the current task to be woken
+    /// once an item is added if it is empty.
+    ///
+    /// Returns [`Poll::Ready`] with a non-empty batch or [`Poll::Pending`]. A task
+    /// that is polled again before it is woken isn't registered twice (see
+    /// [`WakerSet::add_unique`]).
+    pub fn poll_drain(&self, cx: &mut Context<'_>) -> Poll<Drain<T>> {
+        let drain = self.sack.drain();
+        if !drain.is_empty() {
+            return Poll::Ready(drain);
+        }
+
+        self.consumers.add_unique(cx.waker());
+        atomic::fence(Ordering::SeqCst);
+
+        // An item may have been added before the waker was registered.
+        let drain = self.sack.drain();
+        if !drain.is_empty() {
+            return Poll::Ready(drain);
+        }
+        Poll::Pending
+    }
+
+    /// Checks if the sack is empty.
+    ///
+    /// This operation is lock-free.
+    pub fn is_empty(&self) -> bool {
+        self.sack.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Waker},
+        thread,
+        vec::Vec,
+    };
+
+    use super::*;
+    use crate::test_util::{CountingWaker, block_on};
+
+    #[test]
+    fn test_async_sack_wakes_consumer() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let sack = AsyncSack::new();
+        let mut future = pin!(sack.drain_next_batch());
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(counter.count(), 0);
+
+        sack.add(1);
+        sack.add(2);
+        assert_eq!(counter.count(), 1);
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(batch) => assert_eq!(batch.collect::<Vec<_>>(), vec![2, 1]),
+            Poll::Pending => panic!("expected a batch"),
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri gives every use of a vtable its own address")]
+    fn test_async_sack_polled_repeatedly() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let sack = AsyncSack::new();
+        let mut future = pin!(sack.drain_next_batch());
+        for _ in 0..3 {
+            assert!(future.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(sack.consumers.clear(), 1);
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+
+        sack.add(1);
+        sack.add(2);
+        assert_eq!(counter.count(), 1);
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(batch) => assert_eq!(batch.collect::<Vec<_>>(), vec![2, 1]),
+            Poll::Pending => panic!("expected a batch"),
+        }
+    }
+
+    #[test]
+    fn test_async_sack_concurrent() {
+        let sack = Arc::new(AsyncSack::new());
+        let mut handles = Vec::new();
+
+        for i in 0..4 {
+            let sack = Arc::clone(&sack);
+            handles.push(thread::spawn(move || {
+                for j in 0..1000 {
+                    sack.add(i * 1000 + j);
+                }
+            }));
+        }
+
+        let mut items = Vec::new();
+        while items.len() < 4000 {
+            let batch = block_on(sack.drain_next_batch());
+            assert!(!batch.is_empty());
+            items.extend(batch);
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        items.sort();
+        assert_eq!(items, (0..4000).collect::<Vec<_>>());
+    }
+}
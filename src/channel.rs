@@ -0,0 +1,315 @@
+//! An unbounded multi-producer, single-consumer channel that delivers items in batches.
+//!
+//! Sending an item is a single [`Sack::add`], and the receiver takes everything
+//! that has been sent since it last looked in one atomic operation, in the order
+//! it was sent. See [`channel`] for more.
+
+#[cfg(feature = "stream")]
+use core::pin::Pin;
+use core::{
+    future,
+    task::{Context, Poll},
+};
+
+use alloc::sync::Arc;
+
+use crate::{
+    Drain, Sack, WakerSet,
+    sync::atomic::{self, AtomicUsize, Ordering},
+};
+
+/// The state shared by the senders and the receiver.
+struct Shared<T> {
+    sack: Sack<T>,
+    /// Wakers of the receiver waiting for items.
+    receiver: WakerSet,
+    /// The number of live senders.
+    senders: AtomicUsize,
+}
+
+impl<T> Shared<T> {
+    /// Wakes the receiver if it is waiting.
+    fn notify(&self) {
+        // Pairs with the fence in `Receiver::poll_recv`, so that either the
+        // receiver sees the new item or we see its waker.
+        atomic::fence(Ordering::SeqCst);
+        if !self.receiver.is_empty() {
+            self.receiver.wake_all();
+        }
+    }
+}
+
+/// Creates a new unbounded channel, returning the sender and receiver halves.
+///
+/// The channel is closed once all senders or the receiver are dropped.
+///
+/// ## Example
+///
+/// ```
+/// use sack::channel;
+/// use std::thread;
+///
+/// # fn block_on<F: std::future::Future>(future: F) -> F::Output {
+/// #     use std::task::{Context, Poll, Waker};
+/// #     let mut future = std::pin::pin!(future);
+/// #     let mut cx = Context::from_waker(Waker::noop());
+/// #     loop {
+/// #         if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+/// #             return output;
+/// #         }
+/// #         thread::yield_now();
+/// #     }
+/// # }
+/// let (tx, mut rx) = channel::channel();
+///
+/// thread::spawn(move || {
+///     for i in 0..3 {
+///         tx.send(i).unwrap();
+///     }
+/// });
+///
+/// let mut items = Vec::new();
+/// while let Some(batch) = block_on(rx.recv()) {
+///     items.extend(batch);
+/// }
+/// assert_eq!(items, vec![0, 1, 2]);
+/// ```
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        sack: Sack::new(),
+        receiver: WakerSet::new(),
+        senders: AtomicUsize::new(1),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    )
+}
+
+/// The sending half of a [`channel`].
+///
+/// Senders can be cloned to send from multiple threads.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends an item to the receiver.
+    ///
+    /// Returns the item back as an error if the receiver has been dropped.
+    ///
+    /// This operation is lock-free and never blocks.
+    pub fn send(&self, item: T) -> Result<(), T> {
+        self.shared.sack.try_add(item)?;
+        self.shared.notify();
+        Ok(())
+    }
+
+    /// Checks if the receiver has been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.shared.sack.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.sack.close();
+            self.shared.notify();
+        }
+    }
+}
+
+/// The receiving half of a [`channel`].
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receives all items sent so far, without waiting.
+    ///
+    /// The items are yielded in the order they were sent. The returned iterator is
+    /// empty if there are none.
+    pub fn try_recv(&mut self) -> Drain<T> {
+        self.shared.sack.drain_fifo()
+    }
+
+    /// Waits until items are sent and receives all of them.
+    ///
+    /// The items are yielded in the order they were sent, and there is at least one.
+    /// Returns `None` once all senders have been dropped and every item has been
+    /// received.
+    pub async fn recv(&mut self) -> Option<Drain<T>> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Receives all items sent so far, or registers the current task to be woken
+    /// once an item is sent if there are none.
+    ///
+    /// A task that is polled again before it is woken isn't registered twice (see
+    /// [`WakerSet::add_unique`]). See
+    /// [`Receiver::recv`] for details.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Drain<T>>> {
+        if let Poll::Ready(batch) = self.try_poll() {
+            return Poll::Ready(batch);
+        }
+
+        self.shared.receiver.add_unique(cx.waker());
+        atomic::fence(Ordering::SeqCst);
+
+        // An item may have been sent before the waker was registered.
+        self.try_poll()
+    }
+
+    /// Receives all items sent so far, or returns `None` if the channel is closed
+    /// and there are none left.
+    fn try_poll(&mut self) -> Poll<Option<Drain<T>>> {
+        let batch = self.shared.sack.drain_fifo();
+        if !batch.is_empty() {
+            return Poll::Ready(Some(batch));
+        }
+        if self.shared.sack.is_closed() {
+            // No item can be sent anymore, so this is the final batch.
+            let batch = self.shared.sack.drain_fifo();
+            return Poll::Ready((!batch.is_empty()).then_some(batch));
+        }
+        Poll::Pending
+    }
+
+    /// Checks if all senders have been dropped.
+    ///
+    /// Items that were sent before may still be waiting to be received.
+    pub fn is_closed(&self) -> bool {
+        self.shared.sack.is_closed()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.sack.close();
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T> futures_core::Stream for Receiver<T> {
+    type Item = Drain<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        task::{Context, Waker},
+        thread,
+        vec::Vec,
+    };
+
+    use super::*;
+    use crate::test_util::{CountingWaker, block_on};
+
+    #[test]
+    fn test_channel() {
+        let (tx, mut rx) = channel();
+        assert!(rx.try_recv().is_empty());
+
+        let tx2 = tx.clone();
+        tx.send(1).unwrap();
+        tx2.send(2).unwrap();
+        tx.send(3).unwrap();
+        assert_eq!(rx.try_recv().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        tx.send(4).unwrap();
+        drop(tx);
+        assert!(!rx.is_closed());
+        drop(tx2);
+        assert!(rx.is_closed());
+        assert_eq!(block_on(rx.recv()).unwrap().collect::<Vec<_>>(), vec![4]);
+        assert!(block_on(rx.recv()).is_none());
+    }
+
+    #[test]
+    fn test_channel_receiver_dropped() {
+        let (tx, rx) = channel();
+        tx.send(1).unwrap();
+        assert!(!tx.is_closed());
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(2), Err(2));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri gives every use of a vtable its own address")]
+    fn test_channel_polled_repeatedly() {
+        let (tx, mut rx) = channel::<i32>();
+        let waker = Waker::from(CountingWaker::new());
+        let mut cx = Context::from_waker(&waker);
+        for _ in 0..3 {
+            assert!(rx.poll_recv(&mut cx).is_pending());
+        }
+        assert_eq!(tx.shared.receiver.clear(), 1);
+    }
+
+    #[test]
+    fn test_channel_concurrent() {
+        let (tx, mut rx) = channel();
+        let mut handles = Vec::new();
+
+        for i in 0..4 {
+            let tx = tx.clone();
+            handles.push(thread::spawn(move || {
+                for j in 0..1000 {
+                    tx.send((i, j)).unwrap();
+                }
+            }));
+        }
+        drop(tx);
+
+        let mut received = [0; 4];
+        while let Some(batch) = block_on(rx.recv()) {
+            for (i, j) in batch {
+                // Items from the same sender arrive in the order they were sent.
+                assert_eq!(received[i], j);
+                received[i] += 1;
+            }
+        }
+        assert_eq!(received, [1000; 4]);
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn test_channel_stream() {
+        use futures_core::Stream;
+        use std::task::Poll;
+
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        drop(tx);
+
+        let mut rx = Pin::new(&mut rx);
+        let waker = Waker::noop();
+        let mut cx = Context::from_waker(waker);
+        match rx.as_mut().poll_next(&mut cx) {
+            Poll::Ready(Some(batch)) => assert_eq!(batch.collect::<Vec<_>>(), vec![1]),
+            _ => panic!("expected a batch"),
+        }
+        assert!(matches!(rx.poll_next(&mut cx), Poll::Ready(None)));
+    }
+}
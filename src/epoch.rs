@@ -0,0 +1,257 @@
+//! Minimal epoch-based memory reclamation.
+//!
+//! Entries that are unlinked while other threads might still be reading them
+//! (see [`Sack::pop`](crate::Sack::pop)) can't be freed right away. Instead
+//! they are retired here and freed once every thread that was pinned at the
+//! time has unpinned.
+//!
+//! The domain is global so that detached chains (e.g. a [`Drain`](crate::Drain))
+//! don't need to keep a reference to the sack they came from. Whether a chain
+//! can be reused right away is decided per sack instead (see `Sack::read`), so
+//! a thread that stays pinned only delays freeing memory.
+
+use core::ptr;
+
+use alloc::boxed::Box;
+
+use crate::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+#[cfg(not(loom))]
+mod globals {
+    use super::*;
+
+    /// The global epoch.
+    pub(super) static EPOCH: AtomicUsize = AtomicUsize::new(0);
+    /// The number of live [`Guard`]s.
+    pub(super) static PINNED: AtomicUsize = AtomicUsize::new(0);
+    /// Per-guard records. Records are never freed, only reused.
+    pub(super) static LOCALS: AtomicPtr<Local> = AtomicPtr::new(ptr::null_mut());
+    /// Retired objects waiting to be freed.
+    pub(super) static GARBAGE: AtomicPtr<Garbage> = AtomicPtr::new(ptr::null_mut());
+}
+
+// Loom's atomics can't be created in a `static`, and each model run needs its own.
+#[cfg(loom)]
+mod globals {
+    use super::*;
+
+    loom::lazy_static! {
+        /// The global epoch.
+        pub(super) static ref EPOCH: AtomicUsize = AtomicUsize::new(0);
+        /// The number of live [`Guard`]s.
+        pub(super) static ref PINNED: AtomicUsize = AtomicUsize::new(0);
+        /// Per-guard records. Records are never freed, only reused.
+        pub(super) static ref LOCALS: AtomicPtr<Local> = AtomicPtr::new(ptr::null_mut());
+        /// Retired objects waiting to be freed.
+        pub(super) static ref GARBAGE: AtomicPtr<Garbage> = AtomicPtr::new(ptr::null_mut());
+    }
+}
+
+use globals::{EPOCH, GARBAGE, LOCALS, PINNED};
+
+/// The state of a [`Local`] that isn't pinned.
+const UNPINNED: usize = 0;
+
+/// A record of a pinned participant.
+struct Local {
+    /// Either [`UNPINNED`] or the epoch this record is pinned in, shifted left by
+    /// one with the lowest bit set.
+    state: AtomicUsize,
+    /// Whether a guard currently owns this record.
+    in_use: AtomicBool,
+    /// The next record in [`LOCALS`].
+    next: *mut Local,
+}
+
+/// An object waiting to be freed.
+struct Garbage {
+    /// The object to free.
+    ptr: *mut u8,
+    /// The function that frees `ptr`.
+    free: unsafe fn(*mut u8),
+    /// The epoch the object was retired in.
+    epoch: usize,
+    /// The next object in [`GARBAGE`].
+    next: *mut Garbage,
+}
+
+/// A guard that keeps retired objects alive while it exists.
+///
+/// Pointers loaded while pinned stay valid until the guard is dropped.
+pub(crate) struct Guard {
+    local: &'static Local,
+}
+
+/// Pins the current thread.
+pub(crate) fn pin() -> Guard {
+    PINNED.fetch_add(1, Ordering::SeqCst);
+    let local = acquire_local();
+    let epoch = EPOCH.load(Ordering::SeqCst);
+    local.state.store((epoch << 1) | 1, Ordering::SeqCst);
+    atomic::fence(Ordering::SeqCst);
+    Guard { local }
+}
+
+/// Checks whether any thread is pinned.
+///
+/// If this returns `false` after an object has been unlinked, no thread can
+/// still be reading it and it may be freed immediately.
+fn is_pinned() -> bool {
+    atomic::fence(Ordering::SeqCst);
+    PINNED.load(Ordering::SeqCst) != 0
+}
+
+/// Frees `ptr` with `free` once no pinned thread can still be reading it.
+///
+/// # Safety
+///
+/// `ptr` must already be unreachable for threads that pin from now on, and
+/// `free(ptr)` must be safe to call from any thread.
+pub(crate) unsafe fn retire(ptr: *mut u8, free: unsafe fn(*mut u8)) {
+    if !is_pinned() {
+        unsafe { free(ptr) };
+        return;
+    }
+    defer(ptr, free);
+    collect();
+}
+
+impl Guard {
+    /// Like [`retire`], but accounts for this guard being pinned itself.
+    ///
+    /// # Safety
+    ///
+    /// See [`retire`].
+    pub(crate) unsafe fn retire(&self, ptr: *mut u8, free: unsafe fn(*mut u8)) {
+        atomic::fence(Ordering::SeqCst);
+        if PINNED.load(Ordering::SeqCst) == 1 {
+            unsafe { free(ptr) };
+            return;
+        }
+        defer(ptr, free);
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.local.state.store(UNPINNED, Ordering::SeqCst);
+        self.local.in_use.store(false, Ordering::Release);
+        PINNED.fetch_sub(1, Ordering::SeqCst);
+
+        if !GARBAGE.load(Ordering::Relaxed).is_null() {
+            collect();
+        }
+    }
+}
+
+/// Takes an unused record from [`LOCALS`], allocating a new one if needed.
+fn acquire_local() -> &'static Local {
+    let mut current = LOCALS.load(Ordering::Acquire);
+    while let Some(local) = unsafe { current.as_ref() } {
+        if local
+            .in_use
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return local;
+        }
+        current = local.next;
+    }
+
+    // Only go through the raw pointer until the record is published, so that no
+    // unique reference to it is alive once other threads can see it.
+    let local = Box::into_raw(Box::new(Local {
+        state: AtomicUsize::new(UNPINNED),
+        in_use: AtomicBool::new(true),
+        next: LOCALS.load(Ordering::Acquire),
+    }));
+    loop {
+        let next = unsafe { (*local).next };
+        match LOCALS.compare_exchange_weak(next, local, Ordering::Release, Ordering::Acquire) {
+            Ok(_) => break unsafe { &*local },
+            Err(current) => unsafe { (*local).next = current },
+        }
+    }
+}
+
+/// Pushes an object onto [`GARBAGE`].
+fn defer(ptr: *mut u8, free: unsafe fn(*mut u8)) {
+    let garbage = Box::into_raw(Box::new(Garbage {
+        ptr,
+        free,
+        epoch: EPOCH.load(Ordering::SeqCst),
+        next: ptr::null_mut(),
+    }));
+    unsafe { push_garbage(garbage, garbage) };
+}
+
+/// Pushes the chain from `first` to `last` onto [`GARBAGE`].
+///
+/// `last` is a raw pointer since the chain may be collected by another thread
+/// as soon as it is pushed, even before this returns.
+///
+/// # Safety
+///
+/// `first` must be the head of a chain ending in `last` that isn't reachable
+/// by anyone else.
+unsafe fn push_garbage(first: *mut Garbage, last: *mut Garbage) {
+    let mut next = GARBAGE.load(Ordering::Acquire);
+    loop {
+        unsafe { (*last).next = next };
+        match GARBAGE.compare_exchange_weak(next, first, Ordering::Release, Ordering::Acquire) {
+            Ok(_) => break,
+            Err(current) => next = current,
+        }
+    }
+}
+
+/// Advances the global epoch if every pinned record has seen the current one.
+fn try_advance() {
+    let epoch = EPOCH.load(Ordering::SeqCst);
+    atomic::fence(Ordering::SeqCst);
+
+    let mut current = LOCALS.load(Ordering::Acquire);
+    while let Some(local) = unsafe { current.as_ref() } {
+        let state = local.state.load(Ordering::SeqCst);
+        if state != UNPINNED && state != (epoch << 1) | 1 {
+            return;
+        }
+        current = local.next;
+    }
+
+    let _ = EPOCH.compare_exchange(
+        epoch,
+        epoch.wrapping_add(1),
+        Ordering::SeqCst,
+        Ordering::Relaxed,
+    );
+}
+
+/// Frees every retired object that can no longer be read by a pinned thread.
+fn collect() {
+    try_advance();
+    let mut garbage = GARBAGE.swap(ptr::null_mut(), Ordering::AcqRel);
+    // Loaded after taking the list so that no object in it is newer.
+    let epoch = EPOCH.load(Ordering::SeqCst);
+    // Everything taken above was unlinked before anyone who is not pinned now.
+    let quiescent = !is_pinned();
+
+    let mut kept: (*mut Garbage, *mut Garbage) = (ptr::null_mut(), ptr::null_mut());
+    while let Some(current) = unsafe { garbage.as_mut() } {
+        garbage = current.next;
+        if quiescent || epoch.wrapping_sub(current.epoch) >= 2 {
+            let current = unsafe { Box::from_raw(current) };
+            unsafe { (current.free)(current.ptr) };
+        } else {
+            current.next = kept.0;
+            if kept.1.is_null() {
+                kept.1 = current;
+            }
+            kept.0 = current;
+        }
+    }
+
+    if !kept.1.is_null() {
+        unsafe { push_garbage(kept.0, kept.1) };
+    }
+}
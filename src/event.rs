@@ -0,0 +1,313 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    WakerKey, WakerSet,
+    sync::{
+        atomic::{self, AtomicUsize, Ordering},
+        const_fn,
+    },
+};
+
+/// An event that tasks can wait for.
+///
+/// Unlike checking a condition and then registering a waker in a [`WakerSet`],
+/// listening for an event can't miss a notification: a listener created with
+/// [`Event::listen`] remembers the generation of the event at that time, and
+/// completes once it has changed, even if the notification came before it was
+/// first polled.
+///
+/// ## Example
+///
+/// ```
+/// use sack::Event;
+/// use std::sync::atomic::{AtomicBool, Ordering};
+/// use std::task::{Context, Poll, Waker};
+/// use std::pin::pin;
+///
+/// let event = Event::new();
+/// let ready = AtomicBool::new(false);
+///
+/// // Create the listener before checking the condition.
+/// let mut listener = pin!(event.listen());
+/// assert!(!ready.load(Ordering::SeqCst));
+///
+/// // The condition changes before the listener is polled.
+/// ready.store(true, Ordering::SeqCst);
+/// event.notify_all();
+///
+/// let mut cx = Context::from_waker(Waker::noop());
+/// assert_eq!(listener.as_mut().poll(&mut cx), Poll::Ready(()));
+/// ```
+#[derive(Default)]
+pub struct Event {
+    /// Incremented by every [`Event::notify_all`].
+    generation: AtomicUsize,
+    /// The number of [`Event::notify_one`] calls that haven't completed a listener yet.
+    permits: AtomicUsize,
+    listeners: WakerSet,
+}
+
+impl Event {
+    const_fn! {
+        /// Creates a new event.
+        pub const fn new() -> Self {
+            Self {
+                generation: AtomicUsize::new(0),
+                permits: AtomicUsize::new(0),
+                listeners: WakerSet::new(),
+            }
+        }
+    }
+
+    /// Returns a future that completes once the event is notified.
+    ///
+    /// The listener is notified by any [`Event::notify_all`] that happens after
+    /// this call, so it should be created before checking the condition it waits
+    /// for.
+    pub fn listen(&self) -> EventListener<'_> {
+        EventListener {
+            event: self,
+            generation: self.generation.load(Ordering::SeqCst),
+            key: None,
+            waker: None,
+            done: false,
+        }
+    }
+
+    /// Notifies all current listeners.
+    ///
+    /// Returns the number of listeners that were woken. Listeners that haven't
+    /// been polled yet complete as well, but aren't counted.
+    pub fn notify_all(&self) -> usize {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.listeners.wake_all()
+    }
+
+    /// Notifies a single listener.
+    ///
+    /// Every call completes exactly one listener: the one that has been waiting
+    /// the longest if there is any, otherwise the next one that is polled.
+    ///
+    /// Returns `true` if a waiting listener was woken. This is only a hint: if
+    /// another thread is waking listeners at the same time, it may wake one on
+    /// behalf of this call, which then returns `false` (see [`WakerSet::wake_n`]).
+    /// The notification itself is never lost either way.
+    pub fn notify_one(&self) -> bool {
+        self.permits.fetch_add(1, Ordering::SeqCst);
+        self.listeners.wake_one()
+    }
+}
+
+/// A future that completes once an [`Event`] is notified.
+///
+/// This struct is created by [`Event::listen`]. See its documentation for more.
+pub struct EventListener<'a> {
+    event: &'a Event,
+    /// The generation of the event when the listener was created.
+    generation: usize,
+    /// The registration of the waker from the last poll.
+    key: Option<WakerKey<'a>>,
+    /// The registered waker, to tell if the next poll can keep the registration.
+    waker: Option<Waker>,
+    /// Whether the listener has completed.
+    done: bool,
+}
+
+impl EventListener<'_> {
+    /// Checks if the listener has been notified, taking a permit of
+    /// [`Event::notify_one`] if needed.
+    fn try_complete(&mut self) -> bool {
+        self.done = self.event.generation.load(Ordering::SeqCst) != self.generation
+            || self
+                .event
+                .permits
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |permits| {
+                    permits.checked_sub(1)
+                })
+                .is_ok();
+        if self.done {
+            self.key = None;
+        }
+        self.done
+    }
+}
+
+impl Future for EventListener<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if this.done || this.try_complete() {
+            return Poll::Ready(());
+        }
+
+        // A task that is polled again before it is woken stays registered.
+        let registered = this.key.as_ref().is_some_and(|key| !key.is_woken())
+            && this
+                .waker
+                .as_ref()
+                .is_some_and(|waker| waker.will_wake(cx.waker()));
+        if !registered {
+            // Replacing the key cancels the previous registration.
+            this.key = Some(this.event.listeners.register(cx.waker()));
+            this.waker = Some(cx.waker().clone());
+        }
+        atomic::fence(Ordering::SeqCst);
+
+        // The event may have been notified before the waker was registered.
+        if this.try_complete() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for EventListener<'_> {
+    fn drop(&mut self) {
+        // If we were woken by `notify_one` but won't take its permit, pass the
+        // wakeup on to another listener.
+        if !self.done && self.key.as_ref().is_some_and(WakerKey::is_woken) {
+            self.event.listeners.wake_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        boxed::Box,
+        pin::pin,
+        sync::Arc,
+        task::{Context, Waker},
+        thread,
+        vec::Vec,
+    };
+
+    use super::*;
+    use crate::test_util::{CountingWaker, block_on};
+
+    #[test]
+    fn test_event_notify_all() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let event = Event::new();
+        let mut first = pin!(event.listen());
+        let mut second = pin!(event.listen());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+
+        assert_eq!(event.notify_all(), 1);
+        assert_eq!(counter.count(), 1);
+        assert!(first.as_mut().poll(&mut cx).is_ready());
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+
+        // Listeners created afterwards wait for the next notification.
+        let mut third = pin!(event.listen());
+        assert!(third.as_mut().poll(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn test_event_notify_one() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let event = Event::new();
+        let mut first = pin!(event.listen());
+        let mut second = Box::pin(event.listen());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+
+        assert!(event.notify_one());
+        assert!(first.as_mut().poll(&mut cx).is_ready());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+
+        // Without a waiting listener, the next one to be polled completes.
+        drop(second);
+        assert!(!event.notify_one());
+        assert!(pin!(event.listen()).poll(&mut cx).is_ready());
+        assert!(pin!(event.listen()).poll(&mut cx).is_pending());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri gives every use of a vtable its own address")]
+    fn test_event_polled_repeatedly() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let event = Event::new();
+        let mut listener = pin!(event.listen());
+        assert!(listener.as_mut().poll(&mut cx).is_pending());
+        let clones = Arc::strong_count(&counter);
+        for _ in 0..3 {
+            assert!(listener.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(Arc::strong_count(&counter), clones);
+
+        // Another waker replaces the registration.
+        let other = CountingWaker::new();
+        let other_waker = Waker::from(other.clone());
+        let mut other_cx = Context::from_waker(&other_waker);
+        assert!(listener.as_mut().poll(&mut other_cx).is_pending());
+        assert!(event.notify_one());
+        assert_eq!((counter.count(), other.count()), (0, 1));
+        assert!(listener.as_mut().poll(&mut other_cx).is_ready());
+    }
+
+    #[test]
+    fn test_event_listener_dropped() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let event = Event::new();
+        let mut first = Box::pin(event.listen());
+        let mut second = pin!(event.listen());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+
+        // The first listener is woken, but dropped before taking the permit.
+        event.notify_one();
+        assert_eq!(counter.count(), 1);
+        drop(first);
+        assert_eq!(counter.count(), 2);
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_event_concurrent() {
+        let event = Event::new();
+        let completed = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        block_on(event.listen());
+                        completed.fetch_add(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+
+            for _ in 0..8 {
+                event.notify_one();
+            }
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        });
+        assert_eq!(completed.load(Ordering::SeqCst), 8);
+        assert!(
+            pin!(event.listen())
+                .poll(&mut Context::from_waker(Waker::noop()))
+                .is_pending()
+        );
+    }
+}
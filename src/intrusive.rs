@@ -0,0 +1,282 @@
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+/// A link that lets a node be added to an [`IntrusiveSack`].
+///
+/// Embed a `Link` in your own type and implement [`Linked`] for it. A node can
+/// be in at most one sack at a time.
+pub struct Link {
+    /// A pointer to the next link in the sack.
+    next: AtomicPtr<Link>,
+    /// The node this link was pushed with.
+    node: AtomicPtr<()>,
+    /// Whether the link is currently in a sack.
+    linked: AtomicBool,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Link {
+    /// Creates a new, unlinked link.
+    pub const fn new() -> Self {
+        Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            node: AtomicPtr::new(ptr::null_mut()),
+            linked: AtomicBool::new(false),
+        }
+    }
+
+    /// Checks if the link is currently in a sack.
+    pub fn is_linked(&self) -> bool {
+        self.linked.load(Ordering::Acquire)
+    }
+}
+
+/// A type that embeds a [`Link`] and can therefore be added to an [`IntrusiveSack`].
+pub trait Linked {
+    /// Returns the link embedded in this node.
+    fn link(&self) -> &Link;
+}
+
+/// A lock-free sack that doesn't allocate.
+///
+/// This works like [`Sack<T>`](crate::Sack), but instead of boxing each item,
+/// the caller owns the nodes and each node embeds the [`Link`] that chains it
+/// into the sack. Nodes are borrowed for the lifetime of the sack, so they can't
+/// be moved or dropped while they might be linked. This makes it usable on
+/// targets without a heap, and it is available without the `alloc` feature.
+///
+/// Adding and draining nodes are lock-free operations and can be called by
+/// multiple threads concurrently.
+///
+/// ## Example
+///
+/// ```
+/// use sack::{IntrusiveSack, Link, Linked};
+///
+/// struct Job {
+///     id: u32,
+///     link: Link,
+/// }
+///
+/// impl Linked for Job {
+///     fn link(&self) -> &Link {
+///         &self.link
+///     }
+/// }
+///
+/// static JOBS: [Job; 2] = [
+///     Job { id: 0, link: Link::new() },
+///     Job { id: 1, link: Link::new() },
+/// ];
+/// static QUEUE: IntrusiveSack<'static, Job> = IntrusiveSack::new();
+///
+/// assert!(QUEUE.add(&JOBS[0]));
+/// assert!(QUEUE.add(&JOBS[1]));
+/// // A node can't be added twice.
+/// assert!(!QUEUE.add(&JOBS[1]));
+///
+/// let ids: Vec<_> = QUEUE.drain().map(|job| job.id).collect();
+/// assert_eq!(ids, [1, 0]);
+///
+/// // Drained nodes can be added again.
+/// assert!(QUEUE.add(&JOBS[1]));
+/// ```
+pub struct IntrusiveSack<'a, N> {
+    head: AtomicPtr<Link>,
+    _marker: PhantomData<&'a N>,
+}
+
+impl<N: Linked> Default for IntrusiveSack<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, N: Linked> IntrusiveSack<'a, N> {
+    /// Creates a new, empty sack.
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Adds a node to the sack.
+    ///
+    /// Returns `false` without adding it if the node is already in a sack.
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    pub fn add(&self, node: &'a N) -> bool {
+        let link = node.link();
+        if link
+            .linked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        link.node
+            .store(ptr::from_ref(node).cast_mut().cast(), Ordering::Relaxed);
+
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            link.next.store(head, Ordering::Relaxed);
+            match self.head.compare_exchange_weak(
+                head,
+                ptr::from_ref(link).cast_mut(),
+                Ordering::Release,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break true,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Drains all nodes from the sack.
+    ///
+    /// This operation is lock-free and returns a draining iterator over the nodes in the sack.
+    pub fn drain(&self) -> IntrusiveDrain<'a, N> {
+        IntrusiveDrain {
+            head: self.head.swap(ptr::null_mut(), Ordering::AcqRel),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Checks if the sack is empty.
+    ///
+    /// This operation is lock-free.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+}
+
+impl<N> Drop for IntrusiveSack<'_, N> {
+    fn drop(&mut self) {
+        // Unlink the remaining nodes so they can be added to another sack.
+        drop(IntrusiveDrain::<N> {
+            head: *self.head.get_mut(),
+            _marker: PhantomData,
+        });
+    }
+}
+
+/// A draining iterator for [`IntrusiveSack`].
+///
+/// This struct is created by [`IntrusiveSack::drain`]. See its documentation for more.
+/// Nodes that aren't yielded are unlinked when it is dropped.
+pub struct IntrusiveDrain<'a, N> {
+    head: *mut Link,
+    _marker: PhantomData<&'a N>,
+}
+
+unsafe impl<N: Sync> Send for IntrusiveDrain<'_, N> {}
+unsafe impl<N: Sync> Sync for IntrusiveDrain<'_, N> {}
+
+impl<'a, N> Iterator for IntrusiveDrain<'a, N> {
+    type Item = &'a N;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // SAFETY: linked nodes are borrowed for `'a`.
+        let link = unsafe { self.head.as_ref() }?;
+        self.head = link.next.load(Ordering::Relaxed);
+        let node = link.node.load(Ordering::Relaxed);
+        // The link may be added again from here on.
+        link.linked.store(false, Ordering::Release);
+        Some(unsafe { &*node.cast_const().cast() })
+    }
+}
+impl<N> Drop for IntrusiveDrain<'_, N> {
+    fn drop(&mut self) {
+        self.by_ref().for_each(drop);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Barrier, thread, vec::Vec};
+
+    use super::*;
+
+    struct Node {
+        value: usize,
+        link: Link,
+    }
+
+    impl Linked for Node {
+        fn link(&self) -> &Link {
+            &self.link
+        }
+    }
+
+    fn nodes(count: usize) -> Vec<Node> {
+        (0..count)
+            .map(|value| Node {
+                value,
+                link: Link::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_intrusive_sack() {
+        let nodes = nodes(3);
+        let sack = IntrusiveSack::new();
+        assert!(sack.is_empty());
+        for node in &nodes {
+            assert!(sack.add(node));
+        }
+        assert!(!sack.add(&nodes[0]));
+        assert!(!sack.is_empty());
+
+        let mut drain = sack.drain();
+        assert_eq!(drain.next().map(|node| node.value), Some(2));
+        assert!(!nodes[2].link.is_linked());
+        assert!(nodes[1].link.is_linked());
+        drop(drain);
+        assert!(nodes.iter().all(|node| !node.link.is_linked()));
+        assert!(sack.is_empty());
+
+        let other = IntrusiveSack::new();
+        assert!(other.add(&nodes[0]));
+        assert!(!sack.add(&nodes[0]));
+        drop(other);
+        assert!(sack.add(&nodes[0]));
+    }
+
+    #[test]
+    fn test_intrusive_sack_concurrent() {
+        let nodes = nodes(400);
+        let sack = IntrusiveSack::new();
+        let barrier = Barrier::new(4);
+
+        let mut drained: Vec<_> = thread::scope(|s| {
+            for chunk in nodes.chunks(100) {
+                let sack = &sack;
+                let barrier = &barrier;
+                s.spawn(move || {
+                    barrier.wait();
+                    for node in chunk {
+                        assert!(sack.add(node));
+                    }
+                });
+            }
+            let mut drained = Vec::new();
+            while drained.len() < nodes.len() {
+                drained.extend(sack.drain().map(|node| node.value));
+            }
+            drained
+        });
+
+        drained.sort();
+        assert_eq!(drained, (0..400).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,2700 @@
+use core::{alloc::Layout, marker::PhantomData, mem, ptr};
+#[cfg(feature = "std")]
+use core::{hint, time::Duration};
+#[cfg(feature = "std")]
+use std::{
+    thread::{self, Thread},
+    time::Instant,
+};
+
+use alloc::{boxed::Box, sync::Arc};
+
+#[cfg(feature = "std")]
+use crate::sync::atomic::AtomicBool;
+use crate::{
+    epoch,
+    sync::{
+        atomic::{self, AtomicPtr, AtomicUsize, Ordering},
+        const_fn,
+    },
+};
+
+/// The tag set on the head of a sack once it is closed.
+///
+/// Entries are at least pointer-aligned, so the lowest bit of their address is
+/// always free.
+const CLOSED: usize = 1;
+
+/// Strips the [`CLOSED`] tag from a pointer.
+fn untag<T>(ptr: *mut T) -> *mut T {
+    ptr.map_addr(|addr| addr & !CLOSED)
+}
+
+/// A single entry in the sack.
+struct Entry<T> {
+    /// The item stored in the entry.
+    item: T,
+    /// A pointer to the next entry in the sack.
+    ///
+    /// This is atomic since it may be relinked (see [`Sack::drain_fifo`]) while a
+    /// concurrent [`Sack::pop`] is still reading it.
+    next: AtomicPtr<Entry<T>>,
+}
+
+impl<T> Entry<T> {
+    /// Frees an entry whose item has already been moved out.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an entry allocated by [`Sack::add`] that is no longer
+    /// reachable by anyone else.
+    unsafe fn free(ptr: *mut u8) {
+        unsafe { alloc::alloc::dealloc(ptr, Layout::new::<Self>()) };
+    }
+
+    /// Pushes a private chain of entries from `first` to `last` onto `head`.
+    ///
+    /// Returns the previous head, or `None` without pushing if `head` is [`CLOSED`].
+    ///
+    /// # Safety
+    ///
+    /// `first` must be the head of a chain ending in `last` that isn't reachable
+    /// by anyone else.
+    unsafe fn push(head: &AtomicPtr<Self>, first: *mut Self, last: *mut Self) -> Option<*mut Self> {
+        // Only touch `next`, since the items of spare entries are uninitialized.
+        let next = unsafe { &(*last).next };
+        let mut current = head.load(Ordering::Acquire);
+        loop {
+            if current.addr() & CLOSED != 0 {
+                break None;
+            }
+            next.store(current, Ordering::Relaxed);
+            match head.compare_exchange_weak(current, first, Ordering::Release, Ordering::Acquire) {
+                Ok(_) => break Some(current),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Counts the entries in a detached chain.
+    ///
+    /// # Safety
+    ///
+    /// `head` must be null or the head of a chain that stays valid during the call.
+    unsafe fn count(mut head: *const Self) -> usize {
+        let mut count = 0;
+        while let Some(entry) = unsafe { head.as_ref() } {
+            count += 1;
+            head = entry.next.load(Ordering::Relaxed);
+        }
+        count
+    }
+
+    /// Reverses a detached chain of entries in place, returning the new head and
+    /// the number of entries.
+    ///
+    /// # Safety
+    ///
+    /// `head` must be null or the head of a chain no one else is consuming.
+    unsafe fn reverse(mut head: *mut Self) -> (*mut Self, usize) {
+        let mut prev = ptr::null_mut();
+        let mut count = 0;
+        while let Some(entry) = unsafe { head.as_ref() } {
+            let next = entry.next.swap(prev, Ordering::Relaxed);
+            prev = head;
+            head = next;
+            count += 1;
+        }
+        (prev, count)
+    }
+
+    /// Frees a chain of entries whose items have all been moved out.
+    ///
+    /// # Safety
+    ///
+    /// See [`Entry::free`].
+    unsafe fn free_chain(mut ptr: *mut u8) {
+        while !ptr.is_null() {
+            let next = unsafe { (*ptr.cast::<Self>()).next.load(Ordering::Relaxed) };
+            unsafe { Self::free(ptr) };
+            ptr = next.cast();
+        }
+    }
+}
+
+/// A lock-free sack data structure.
+///
+/// A sack is a concurrent data structure that allows adding items and draining
+/// them in a lock-free manner. It is implemented as a singly-linked list where
+/// the head is an atomic pointer. This allows multiple producers to add items
+/// concurrently without locks.
+///
+/// ## How it works
+///
+/// The `Sack` is essentially a LIFO (last-in, first-out) stack. When an item is
+/// added, it is pushed to the front of the list. When the sack is drained, the
+/// entire list is atomically swapped with an empty list, and the old list is
+/// returned as a draining iterator, either as is or reversed with
+/// [`Sack::drain_fifo`] to get the items in insertion order. Single items can
+/// also be taken from the front of the list with [`Sack::pop`], which uses
+/// epoch-based reclamation to make sure entries aren't freed while other threads
+/// are still reading them.
+///
+/// This design has the following properties:
+///
+/// * **Lock-free:** Adding and draining items are lock-free operations, which
+///   means they don't require mutual exclusion. This makes them very fast and
+///   scalable.
+/// * **Concurrent producers:** Multiple threads can add items to the sack
+///   concurrently.
+/// * **Multiple consumers:** Any number of threads can drain the sack or pop
+///   items from it concurrently. Each item is returned exactly once.
+///
+/// A sack can also be [closed](Sack::close), after which no more items can be
+/// added. This is useful for shutting down: [`Sack::close_and_drain`] seals the
+/// sack and takes the final items in one atomic step, so no item added by a
+/// late producer is silently left behind.
+///
+/// ## Example
+///
+/// ```
+/// use sack::Sack;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let sack = Arc::new(Sack::new());
+///
+/// // Spawn a producer thread.
+/// let producer = {
+///     let sack = Arc::clone(&sack);
+///     thread::spawn(move || {
+///         for i in 0..10 {
+///             sack.add(i);
+///         }
+///     })
+/// };
+///
+/// // Wait for the producer to finish.
+/// producer.join().unwrap();
+///
+/// // Drain the sack and collect the items.
+/// let mut items: Vec<_> = sack.drain().collect();
+/// items.sort();
+///
+/// assert_eq!(items, (0..10).collect::<Vec<_>>());
+/// ```
+///
+/// ## Thread safety
+///
+/// Items are moved into the sack by one thread and out of it by another, so a
+/// sack can only be shared between threads if `T: Send`:
+///
+/// ```compile_fail
+/// use sack::Sack;
+/// use std::rc::Rc;
+///
+/// fn assert_sync<T: Sync>(_: &T) {}
+///
+/// assert_sync(&Sack::<Rc<i32>>::new());
+/// ```
+///
+/// ```compile_fail
+/// use sack::Sack;
+/// use std::rc::Rc;
+///
+/// fn assert_send<T: Send>(_: &T) {}
+///
+/// assert_send(&Sack::<Rc<i32>>::new().drain());
+/// ```
+///
+/// `T` doesn't need to be `Sync`, since the sack only hands out shared
+/// references to its items across threads if it is (see [`Sack::for_each_ref`]):
+///
+/// ```
+/// use sack::Sack;
+/// use std::cell::Cell;
+///
+/// fn assert_send_sync<T: Send + Sync>(_: &T) {}
+///
+/// assert_send_sync(&Sack::<Cell<i32>>::new());
+/// ```
+pub struct Sack<T> {
+    head: AtomicPtr<Entry<T>>,
+    /// Entries handed back by [`Recycle`], whose items are uninitialized.
+    spare: AtomicPtr<Entry<T>>,
+    /// The number of items, if tracked (see [`Sack::with_len_tracking`]).
+    ///
+    /// Items are counted before they are pushed and uncounted after they are
+    /// removed, so this never underestimates.
+    len: Option<AtomicUsize>,
+    /// The maximum number of items, or `usize::MAX` if unbounded.
+    capacity: usize,
+    /// The number of items that were added to a bounded sack and haven't been
+    /// consumed yet. It is shared with the drains, which release capacity as
+    /// they yield items.
+    used: Option<Arc<AtomicUsize>>,
+    /// The number of live [`ReadGuard`]s, which might be reading entries that
+    /// are detached concurrently.
+    readers: AtomicUsize,
+    /// Threads blocked in [`Sack::drain_blocking`] or [`Sack::drain_timeout`].
+    ///
+    /// The entries live on the stacks of the blocked threads, which take them
+    /// out again before they return.
+    #[cfg(feature = "std")]
+    sleepers: AtomicPtr<Entry<Sleeper>>,
+}
+
+unsafe impl<T: Send> Send for Sack<T> {}
+unsafe impl<T: Send> Sync for Sack<T> {}
+
+impl<T> Default for Sack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Sack<T> {
+    const_fn! {
+        /// Creates a new, empty sack.
+        pub const fn new() -> Self {
+            Self {
+                head: AtomicPtr::new(ptr::null_mut()),
+                spare: AtomicPtr::new(ptr::null_mut()),
+                len: None,
+                capacity: usize::MAX,
+                used: None,
+                readers: AtomicUsize::new(0),
+                #[cfg(feature = "std")]
+                sleepers: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+    }
+
+    const_fn! {
+        /// Creates a new, empty sack that keeps track of its length.
+        ///
+        /// Tracking costs an extra atomic operation for every add, pop and drain, and
+        /// drains count the items they take out. In exchange, [`Sack::len`] can
+        /// report the number of items at any time, and the length of a [`Drain`] is
+        /// known up front instead of being counted when it is asked for.
+        ///
+        /// ## Example
+        ///
+        /// ```
+        /// use sack::Sack;
+        ///
+        /// let sack = Sack::with_len_tracking();
+        /// assert_eq!(sack.len(), Some(0));
+        /// sack.add_all(0..3);
+        /// assert_eq!(sack.len(), Some(3));
+        /// sack.pop();
+        /// assert_eq!(sack.len(), Some(2));
+        ///
+        /// assert_eq!(Sack::<i32>::new().len(), None);
+        /// ```
+        pub const fn with_len_tracking() -> Self {
+            Self {
+                head: AtomicPtr::new(ptr::null_mut()),
+                spare: AtomicPtr::new(ptr::null_mut()),
+                len: Some(AtomicUsize::new(0)),
+                capacity: usize::MAX,
+                used: None,
+                readers: AtomicUsize::new(0),
+                #[cfg(feature = "std")]
+                sleepers: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+    }
+
+    /// Creates a new, empty sack that holds at most `capacity` items.
+    ///
+    /// Adding items to a full sack fails (see [`Sack::try_add`]) until some are
+    /// consumed. Capacity is released as items are consumed, i.e. when they are
+    /// popped, or yielded or dropped by a [`Drain`], so a slow consumer holding
+    /// a large drain keeps producers from adding more.
+    ///
+    /// A bounded sack also keeps track of its length (see [`Sack::with_len_tracking`]),
+    /// which only counts the items that are still in the sack.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::bounded(2);
+    /// assert_eq!(sack.try_add(1), Ok(()));
+    /// assert_eq!(sack.try_add(2), Ok(()));
+    /// assert_eq!(sack.try_add(3), Err(3));
+    ///
+    /// let mut drain = sack.drain();
+    /// assert_eq!(sack.len(), Some(0));
+    /// assert_eq!(sack.try_add(3), Err(3));
+    /// drain.next();
+    /// assert_eq!(sack.try_add(3), Ok(()));
+    /// ```
+    pub fn bounded(capacity: usize) -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            spare: AtomicPtr::new(ptr::null_mut()),
+            len: Some(AtomicUsize::new(0)),
+            capacity,
+            used: Some(Arc::new(AtomicUsize::new(0))),
+            readers: AtomicUsize::new(0),
+            #[cfg(feature = "std")]
+            sleepers: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Adds an item to the sack.
+    ///
+    /// **If the sack has been [closed](Sack::close) or is [full](Sack::bounded),
+    /// the item is silently dropped.** Use [`Sack::try_add`] to get it back,
+    /// which is almost always what a bounded sack needs.
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    pub fn add(&self, item: T) {
+        let _ = self.try_add(item);
+    }
+
+    /// Tries to add an item to the sack.
+    ///
+    /// Returns the item back as an error if the sack has been [closed](Sack::close)
+    /// or is [full](Sack::bounded).
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::new();
+    /// assert_eq!(sack.try_add(1), Ok(()));
+    /// sack.close();
+    /// assert_eq!(sack.try_add(2), Err(2));
+    /// ```
+    pub fn try_add(&self, item: T) -> Result<(), T> {
+        if !self.track_added(1) {
+            return Err(item);
+        }
+        let entry = self.alloc(item);
+        match unsafe { Entry::push(&self.head, entry, entry) } {
+            Some(prev) => {
+                self.published(prev);
+                Ok(())
+            }
+            None => {
+                self.track_rejected(1);
+                Err(unsafe { Box::from_raw(entry) }.item)
+            }
+        }
+    }
+
+    /// Adds all items from an iterator to the sack.
+    ///
+    /// The items are linked together privately first and then published with a
+    /// single atomic operation, so bulk producers contend for the sack only once.
+    /// The result is the same as calling [`Sack::add`] for each item in order.
+    ///
+    /// **If the sack has been [closed](Sack::close) or doesn't have room for all
+    /// of them, all items are silently dropped.** Use [`Sack::try_add_all`] to
+    /// get them back.
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::new();
+    /// sack.add(0);
+    /// sack.add_all(1..4);
+    ///
+    /// assert_eq!(sack.drain_fifo().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    /// ```
+    pub fn add_all<I: IntoIterator<Item = T>>(&self, items: I) {
+        let _ = self.try_add_all(items);
+    }
+
+    /// Tries to add all items from an iterator to the sack.
+    ///
+    /// Works like [`Sack::add_all`], but if the sack has been [closed](Sack::close)
+    /// or doesn't have room for all of them, none of the items are added and they
+    /// are returned as an error, in the order they were given.
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::bounded(3);
+    /// assert!(sack.try_add_all(0..2).is_ok());
+    ///
+    /// let rejected = sack.try_add_all(2..4).unwrap_err();
+    /// assert_eq!(rejected.collect::<Vec<_>>(), vec![2, 3]);
+    /// assert_eq!(sack.drain_fifo().collect::<Vec<_>>(), vec![0, 1]);
+    /// ```
+    pub fn try_add_all<I: IntoIterator<Item = T>>(&self, items: I) -> Result<(), Drain<T>> {
+        // Frees the entries built so far if the iterator panics.
+        let mut chain = unsafe { Drain::private(ptr::null_mut()) };
+        let mut last: *mut Entry<T> = ptr::null_mut();
+        let mut count = 0;
+        for item in items {
+            let entry = self.alloc(item);
+            unsafe { (*entry).next.store(chain.head, Ordering::Relaxed) };
+            chain.head = entry;
+            if last.is_null() {
+                last = entry;
+            }
+            count += 1;
+        }
+        let first = mem::replace(&mut chain.head, ptr::null_mut());
+        if first.is_null() {
+            return Ok(());
+        }
+        unsafe { self.add_chain(first, last, count) }.map_err(|mut rejected| {
+            rejected.reverse();
+            rejected
+        })
+    }
+
+    /// Moves all items of a drain into the sack, reusing their entries.
+    ///
+    /// The result is the same as calling [`Sack::add_all`] with the drain, but
+    /// the entries are relinked instead of allocating new ones, and published
+    /// with a single atomic operation. Only if a thread was popping items from
+    /// the drained sack when the drain was taken, the items have to be moved into
+    /// new entries.
+    ///
+    /// Like [`Sack::try_add_all`], if the sack has been [closed](Sack::close) or
+    /// doesn't have room for all of the items, none of them are added and they
+    /// are returned as an error, in the order the drain would have yielded them.
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let local = Sack::new();
+    /// local.add_all(0..3);
+    ///
+    /// let global = Sack::new();
+    /// global.add(10);
+    /// assert!(global.append(local.drain_fifo()).is_ok());
+    /// assert_eq!(global.drain_fifo().collect::<Vec<_>>(), vec![10, 0, 1, 2]);
+    ///
+    /// global.close();
+    /// local.add_all(0..3);
+    /// let rejected = global.append(local.drain_fifo()).unwrap_err();
+    /// assert_eq!(rejected.collect::<Vec<_>>(), vec![0, 1, 2]);
+    /// ```
+    pub fn append(&self, mut drain: Drain<T>) -> Result<(), Drain<T>> {
+        if !drain.shared.is_null() {
+            // See `Sack::split_front`.
+            return self.try_add_all(drain);
+        }
+        let last = mem::replace(&mut drain.head, ptr::null_mut());
+        if last.is_null() {
+            return Ok(());
+        }
+        let (first, count) = unsafe { Entry::reverse(last) };
+        match unsafe { self.add_chain(first, last, count) } {
+            Ok(()) => {
+                drain.consumed(count);
+                Ok(())
+            }
+            Err(mut rejected) => {
+                // The items still count against the capacity of their old sack.
+                rejected.used = drain.used.take();
+                rejected.reverse();
+                Err(rejected)
+            }
+        }
+    }
+
+    /// Moves all items of another sack into this one, keeping their order.
+    ///
+    /// This takes the items like [`Sack::drain_fifo`] and adds them like
+    /// [`Sack::append`], so the entries are reused. It is useful for merging
+    /// per-thread sacks into a shared one.
+    ///
+    /// If this sack rejects the items, `other` has already been emptied, so they
+    /// are returned as an error from oldest to newest. They can be put back with
+    /// `other.append(rejected)`.
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let local = Sack::new();
+    /// local.add_all(0..3);
+    ///
+    /// let global = Sack::new();
+    /// assert!(global.append_sack(&local).is_ok());
+    ///
+    /// assert!(local.is_empty());
+    /// assert_eq!(global.drain_fifo().collect::<Vec<_>>(), vec![0, 1, 2]);
+    /// ```
+    pub fn append_sack(&self, other: &Sack<T>) -> Result<(), Drain<T>> {
+        self.append(other.drain_fifo())
+    }
+
+    /// Publishes a private chain of `count` items, or returns it if the sack is
+    /// closed or doesn't have room for all of them.
+    ///
+    /// # Safety
+    ///
+    /// `first` must be the head of a chain ending in `last` that isn't reachable
+    /// by anyone else, and hold `count` items.
+    unsafe fn add_chain(
+        &self,
+        first: *mut Entry<T>,
+        last: *mut Entry<T>,
+        count: usize,
+    ) -> Result<(), Drain<T>> {
+        let added = self.track_added(count);
+        match added.then(|| unsafe { Entry::push(&self.head, first, last) }) {
+            Some(Some(prev)) => {
+                self.published(prev);
+                Ok(())
+            }
+            pushed => {
+                if pushed.is_some() {
+                    self.track_rejected(count);
+                }
+                Err(Drain {
+                    head: first,
+                    len: Some(count),
+                    shared: ptr::null_mut(),
+                    used: None,
+                })
+            }
+        }
+    }
+
+    /// Called after items were pushed onto `prev`, the previous head, to wake the
+    /// threads waiting for the sack to become non-empty.
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    fn published(&self, prev: *mut Entry<T>) {
+        #[cfg(feature = "std")]
+        if prev.is_null() {
+            self.unpark_sleepers();
+        }
+    }
+
+    /// Counts newly added items if the length is tracked.
+    ///
+    /// Returns `false` without counting them if they don't fit in the capacity.
+    fn track_added(&self, count: usize) -> bool {
+        if let Some(used) = &self.used {
+            let reserved = used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(count)
+                    .filter(|&used| used <= self.capacity)
+            });
+            if reserved.is_err() {
+                return false;
+            }
+        }
+        if let Some(len) = &self.len {
+            len.fetch_add(count, Ordering::Relaxed);
+        }
+        true
+    }
+
+    /// Uncounts items that were counted by [`Sack::track_added`] but couldn't
+    /// be pushed.
+    fn track_rejected(&self, count: usize) {
+        self.track_removed(count);
+        self.track_consumed(count);
+    }
+
+    /// Uncounts removed items if the length is tracked.
+    fn track_removed(&self, count: usize) {
+        if let Some(len) = &self.len {
+            len.fetch_sub(count, Ordering::Relaxed);
+        }
+    }
+
+    /// Releases the capacity of consumed items if the sack is bounded.
+    fn track_consumed(&self, count: usize) {
+        if let Some(used) = &self.used {
+            used.fetch_sub(count, Ordering::Relaxed);
+        }
+    }
+
+    /// Creates a private entry for `item`, reusing a spare one if there is any.
+    fn alloc(&self, item: T) -> *mut Entry<T> {
+        let Some(entry) = self.take_spare() else {
+            return Box::into_raw(Box::new(Entry {
+                item,
+                next: AtomicPtr::new(ptr::null_mut()),
+            }));
+        };
+        unsafe { ptr::addr_of_mut!((*entry).item).write(item) };
+        entry
+    }
+
+    /// Takes a single entry from the spare list.
+    ///
+    /// The whole list is taken at once and the rest is put back, so that no
+    /// thread ever reads an entry that someone else might be reusing.
+    fn take_spare(&self) -> Option<*mut Entry<T>> {
+        if self.spare.load(Ordering::Relaxed).is_null() {
+            return None;
+        }
+        let entry = self.spare.swap(ptr::null_mut(), Ordering::Acquire);
+        if entry.is_null() {
+            return None;
+        }
+
+        let rest = unsafe { (*entry).next.load(Ordering::Relaxed) };
+        if !rest.is_null()
+            && self
+                .spare
+                .compare_exchange(ptr::null_mut(), rest, Ordering::Release, Ordering::Relaxed)
+                .is_err()
+        {
+            // Someone handed back more entries in the meantime.
+            let mut last = rest;
+            loop {
+                let next = unsafe { (*last).next.load(Ordering::Relaxed) };
+                if next.is_null() {
+                    break;
+                }
+                last = next;
+            }
+            let _ = unsafe { Entry::push(&self.spare, rest, last) };
+        }
+        Some(entry)
+    }
+
+    /// Removes the most recently added item from the sack.
+    ///
+    /// Other threads might still be reading the entry of the item, so it is only
+    /// freed once no thread is pinned in the epoch it was removed in. Epochs are
+    /// shared by all sacks, so a thread that stays pinned for long, e.g. in
+    /// [`Sack::iter_shared`], delays freeing the entries popped from every sack
+    /// in the meantime. Drains taken while this sack is being popped from copy
+    /// their items when putting them back (see [`Sack::drain_oldest`]).
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::new();
+    /// sack.add(1);
+    /// sack.add(2);
+    ///
+    /// assert_eq!(sack.pop(), Some(2));
+    /// assert_eq!(sack.pop(), Some(1));
+    /// assert_eq!(sack.pop(), None);
+    /// ```
+    pub fn pop(&self) -> Option<T> {
+        let guard = self.read();
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            // SAFETY: entries that were reachable after pinning are not freed
+            // until the guard is dropped.
+            let entry = unsafe { untag(head).as_ref() }?;
+            let next = entry.next.load(Ordering::Relaxed);
+            match self.head.compare_exchange_weak(
+                head,
+                next.map_addr(|addr| addr | (head.addr() & CLOSED)),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    // SAFETY: the entry is unlinked, so its item is ours now.
+                    let item = unsafe { ptr::read(&entry.item) };
+                    unsafe { guard.epoch.retire(untag(head).cast(), Entry::<T>::free) };
+                    self.track_removed(1);
+                    self.track_consumed(1);
+                    return Some(item);
+                }
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Calls `f` on every item in the sack, without taking them out.
+    ///
+    /// Items are visited from newest to oldest while other threads keep adding
+    /// and draining items. Items that are added or taken out concurrently may or
+    /// may not be seen, and the ones that are drained in order (see
+    /// [`Sack::drain_fifo`] and [`Sack::drain_oldest`]) at the same time may be
+    /// seen twice.
+    ///
+    /// The entries are kept alive by pinning the current thread (see
+    /// [`Sack::iter_shared`]). An item can be taken out while `f` looks at it,
+    /// which is why this is limited to `Copy` items: taking one out copies it and
+    /// leaves the entry as it was.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::new();
+    /// sack.add_all([3, 7, 42]);
+    ///
+    /// let mut sum = 0;
+    /// sack.for_each_ref(|&id| sum += id);
+    /// assert_eq!(sum, 52);
+    /// ```
+    pub fn for_each_ref(&self, mut f: impl FnMut(&T))
+    where
+        T: Copy + Sync,
+    {
+        // SAFETY: `T: Copy` has no drop glue and owns nothing, so the item
+        // stays valid while it is taken out.
+        unsafe {
+            self.any(|item| {
+                f(item);
+                false
+            })
+        };
+    }
+
+    /// Returns an iterator over copies of the items, without taking them out.
+    ///
+    /// While the iterator exists, entries drained from this sack can't be freed
+    /// or reused, so the items are copied into new entries instead. Don't keep
+    /// it around longer than needed. See [`Sack::for_each_ref`] for which items
+    /// are seen.
+    ///
+    /// The iterator also pins the current thread in the global epoch (see
+    /// [`Sack::pop`]), which delays freeing the entries popped from any sack
+    /// until it is dropped.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::new();
+    /// sack.add_all([3, 7, 42]);
+    ///
+    /// assert!(sack.iter_shared().any(|id| id == 7));
+    /// assert_eq!(sack.iter_shared().collect::<Vec<_>>(), vec![42, 7, 3]);
+    /// ```
+    pub fn iter_shared(&self) -> SharedIter<'_, T>
+    where
+        T: Copy + Sync,
+    {
+        let guard = self.read();
+        SharedIter {
+            head: untag(self.head.load(Ordering::Acquire)),
+            _guard: guard,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Checks if any item in the sack matches `f`, without taking it out.
+    ///
+    /// Items that are added concurrently may or may not be seen.
+    ///
+    /// # Safety
+    ///
+    /// Items may be taken out and dropped by other threads while `f` looks at
+    /// them, so `f` must only inspect the item itself and not follow any pointers
+    /// in it.
+    pub(crate) unsafe fn any(&self, mut f: impl FnMut(&T) -> bool) -> bool {
+        let _guard = self.read();
+        let mut head = untag(self.head.load(Ordering::Acquire));
+        // SAFETY: entries that were reachable after pinning are not freed until
+        // the guard is dropped. Chains that are reversed concurrently (see
+        // `Sack::drain_fifo`) only ever lead back to the end of the list.
+        while let Some(entry) = unsafe { head.as_ref() } {
+            if f(&entry.item) {
+                return true;
+            }
+            head = entry.next.load(Ordering::Relaxed);
+        }
+        false
+    }
+
+    /// Drains all items from the sack.
+    ///
+    /// This operation is lock-free and returns a draining iterator over the items in the sack.
+    pub fn drain(&self) -> Drain<T> {
+        let head = self.detach();
+        self.detached(head, None)
+    }
+
+    /// Registers the current thread as a reader of the entries, which keeps them
+    /// alive until the returned guard is dropped.
+    fn read(&self) -> ReadGuard<'_> {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        // Pinning ends with a fence, which pairs with the one in `Sack::new_drain`.
+        ReadGuard {
+            readers: &self.readers,
+            epoch: epoch::pin(),
+        }
+    }
+
+    /// Creates a draining iterator from a chain of items that was just detached
+    /// from the sack, and holds `len` items if that is known.
+    fn new_drain(&self, head: *mut Entry<T>, len: Option<usize>) -> Drain<T> {
+        // Either a reader that might still see the chain is counted here, or it
+        // loads the head only after the chain was detached.
+        atomic::fence(Ordering::SeqCst);
+        let shared = if !head.is_null() && self.readers.load(Ordering::SeqCst) != 0 {
+            head
+        } else {
+            ptr::null_mut()
+        };
+        Drain {
+            head,
+            len,
+            shared,
+            used: None,
+        }
+    }
+
+    /// Detaches the whole list, leaving the sack empty but keeping it closed if it was.
+    fn detach(&self) -> *mut Entry<T> {
+        untag(self.head.fetch_and(CLOSED, Ordering::AcqRel))
+    }
+
+    /// Uncounts a detached chain of items and creates a draining iterator from
+    /// it, which releases their capacity as it goes.
+    ///
+    /// The chain is only counted if the length is tracked and `len` isn't known yet.
+    fn detached(&self, head: *mut Entry<T>, len: Option<usize>) -> Drain<T> {
+        let len = len.or_else(|| self.len.is_some().then(|| unsafe { Entry::count(head) }));
+        if let Some(len) = len {
+            self.track_removed(len);
+        }
+        let mut drain = self.new_drain(head, len);
+        drain.used = self.used.clone();
+        drain
+    }
+
+    /// Drains all items from the sack in the order they were added.
+    ///
+    /// This works like [`Sack::drain`], but reverses the detached list in place
+    /// first, so no allocation is needed. Items added concurrently by different
+    /// threads are ordered by when they were actually pushed.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::new();
+    /// sack.add(1);
+    /// sack.add(2);
+    /// sack.add(3);
+    ///
+    /// assert_eq!(sack.drain_fifo().collect::<Vec<_>>(), vec![1, 2, 3]);
+    /// ```
+    pub fn drain_fifo(&self) -> Drain<T> {
+        let (head, len) = unsafe { Entry::reverse(self.detach()) };
+        self.detached(head, Some(len))
+    }
+
+    /// Takes the `n` oldest items out of the sack, leaving the rest in it.
+    ///
+    /// The returned iterator yields the items in the order they were added, like
+    /// [`Sack::drain_fifo`]. Since only the head of the list can be swapped
+    /// atomically, the whole list is detached first and the items that aren't
+    /// taken are put back below any that were added in the meantime. While that
+    /// happens, concurrent calls to [`Sack::pop`] or [`Sack::drain`] don't see them.
+    /// They are put back even if the sack is closed in the meantime, after
+    /// [`Sack::close_and_drain`] has returned without them.
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::new();
+    /// sack.add_all(1..5);
+    ///
+    /// assert_eq!(sack.drain_oldest(2).collect::<Vec<_>>(), vec![1, 2]);
+    /// sack.add(5);
+    /// assert_eq!(sack.drain_fifo().collect::<Vec<_>>(), vec![3, 4, 5]);
+    /// ```
+    pub fn drain_oldest(&self, n: usize) -> Drain<T> {
+        let head = self.detach();
+        let len = unsafe { Entry::count(head) };
+        let mut drain = self.new_drain(head, Some(len));
+        if len > n {
+            let (first, last) = self.split_front(&mut drain, len - n);
+            unsafe { self.restore(first, last) };
+        }
+        self.track_removed(len.min(n));
+        drain.reverse();
+        drain.used = self.used.clone();
+        drain
+    }
+
+    /// Takes the items matching `pred` out of the sack, leaving the rest in it.
+    ///
+    /// The returned iterator yields the matching items in the order they were
+    /// added. The whole list is detached first, and the items that don't match
+    /// are put back in their order with a single compare-and-swap, below any
+    /// that were added in the meantime. While that happens, concurrent calls to
+    /// [`Sack::pop`] or [`Sack::drain`] don't see them. If `pred` panics, all
+    /// items that haven't matched yet are put back.
+    ///
+    /// The items are put back even if the sack is closed while `pred` runs, so a
+    /// concurrent [`Sack::close_and_drain`] doesn't return them. They stay in the
+    /// closed sack until it is drained again.
+    ///
+    /// This operation is lock-free and can be called by multiple threads concurrently.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::new();
+    /// sack.add_all([("a", 1), ("b", 2), ("a", 3), ("b", 4)]);
+    ///
+    /// let jobs: Vec<_> = sack.drain_filter(|&mut (tenant, _)| tenant == "a").collect();
+    /// assert_eq!(jobs, vec![("a", 1), ("a", 3)]);
+    /// assert_eq!(sack.drain_fifo().collect::<Vec<_>>(), vec![("b", 2), ("b", 4)]);
+    /// ```
+    pub fn drain_filter(&self, mut pred: impl FnMut(&mut T) -> bool) -> Drain<T> {
+        let head = self.detach();
+        let mut filter = Filter {
+            sack: self,
+            rest: self.new_drain(head, None),
+            current: ptr::null_mut(),
+            kept: (ptr::null_mut(), ptr::null_mut()),
+        };
+        let mut taken = Drain {
+            head: ptr::null_mut(),
+            len: Some(0),
+            shared: ptr::null_mut(),
+            used: self.used.clone(),
+        };
+        while let Some(entry) = filter.next_entry() {
+            if pred(unsafe { &mut (*entry).item }) {
+                // Entries are visited from newest to oldest, so this ends up in
+                // the order they were added.
+                unsafe { (*entry).next.store(taken.head, Ordering::Relaxed) };
+                taken.head = entry;
+                if let Some(len) = &mut taken.len {
+                    *len += 1;
+                }
+                filter.current = ptr::null_mut();
+                self.track_removed(1);
+            } else {
+                filter.keep();
+            }
+        }
+        drop(filter);
+        taken
+    }
+
+    /// Moves the first `count` items of a detached chain into a private chain,
+    /// returning its first and last entry.
+    ///
+    /// `drain` must have at least `count` items left, and `count` must not be zero.
+    fn split_front(&self, drain: &mut Drain<T>, count: usize) -> (*mut Entry<T>, *mut Entry<T>) {
+        if drain.shared.is_null() {
+            let first = drain.head;
+            let mut last = first;
+            for _ in 1..count {
+                last = unsafe { (*last).next.load(Ordering::Relaxed) };
+            }
+            drain.head = unsafe { (*last).next.swap(ptr::null_mut(), Ordering::Relaxed) };
+            drain.consumed(count);
+            return (first, last);
+        }
+
+        // A concurrent `pop` might still be reading the entries, so publishing them
+        // again would allow it to succeed with a stale `next`. Move the items into
+        // new entries instead and retire the old ones.
+        let emptied = drain.shared;
+        let mut first: *mut Entry<T> = ptr::null_mut();
+        let mut last: *mut Entry<T> = ptr::null_mut();
+        let mut prev = drain.head;
+        for _ in 0..count {
+            prev = drain.head;
+            let entry = self.alloc(drain.next().unwrap());
+            match unsafe { last.as_ref() } {
+                Some(last) => last.next.store(entry, Ordering::Relaxed),
+                None => first = entry,
+            }
+            last = entry;
+        }
+        unsafe { (*prev).next.store(ptr::null_mut(), Ordering::Relaxed) };
+        unsafe { epoch::retire(emptied.cast(), Entry::<T>::free_chain) };
+        drain.shared = drain.head;
+        (first, last)
+    }
+
+    /// Puts a private chain of items that were detached from the sack back below
+    /// any items that have been added since.
+    ///
+    /// # Safety
+    ///
+    /// `first` must be the head of a chain ending in `last` that isn't reachable
+    /// by anyone else.
+    unsafe fn restore(&self, mut first: *mut Entry<T>, last: *mut Entry<T>) {
+        unsafe { (*last).next.store(ptr::null_mut(), Ordering::Relaxed) };
+        let mut current = self.head.load(Ordering::Acquire);
+        loop {
+            if untag(current).is_null() {
+                // Keep the sack closed if it was closed in the meantime.
+                let new = first.map_addr(|addr| addr | current.addr());
+                match self
+                    .head
+                    .compare_exchange(current, new, Ordering::AcqRel, Ordering::Acquire)
+                {
+                    Ok(prev) => {
+                        // Threads that found the sack empty meanwhile wait for this.
+                        self.published(untag(prev));
+                        break;
+                    }
+                    Err(actual) => {
+                        current = actual;
+                        continue;
+                    }
+                }
+            }
+
+            // Newer items have to stay in front, so take them out and link them in.
+            let head = self.detach();
+            let len = unsafe { Entry::count(head) };
+            let mut newer = self.new_drain(head, Some(len));
+            if len > 0 {
+                let (newer_first, newer_last) = self.split_front(&mut newer, len);
+                unsafe { (*newer_last).next.store(first, Ordering::Relaxed) };
+                first = newer_first;
+            }
+            current = self.head.load(Ordering::Acquire);
+        }
+    }
+
+    /// Drains all items from the sack, keeping their entries for reuse.
+    ///
+    /// This works like [`Sack::drain`], but once the returned iterator is dropped
+    /// the entries of all drained items are kept in the sack and reused by later
+    /// calls to [`Sack::add`] and [`Sack::add_all`] instead of allocating. This
+    /// makes steady-state add and drain cycles allocation-free, at the cost of
+    /// holding on to the memory of as many entries as the sack ever held at once
+    /// until it is dropped.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::new();
+    /// for round in 0..3 {
+    ///     sack.add(round);
+    ///     sack.add(round + 1);
+    ///     assert_eq!(sack.drain_recycle().sum::<i32>(), round * 2 + 1);
+    /// }
+    /// ```
+    pub fn drain_recycle(&self) -> Recycle<'_, T> {
+        let drain = self.drain();
+        Recycle {
+            sack: self,
+            first: drain.head,
+            last: ptr::null_mut(),
+            drain,
+        }
+    }
+
+    /// Returns the number of items in the sack, if it was created with
+    /// [`Sack::with_len_tracking`].
+    ///
+    /// This is a snapshot that can be outdated as soon as it is returned. Items
+    /// that are being added concurrently may already be counted. With exclusive
+    /// access, [`Sack::count`] counts the items of any sack.
+    ///
+    /// This operation is lock-free.
+    pub fn len(&self) -> Option<usize> {
+        self.len.as_ref().map(|len| len.load(Ordering::Relaxed))
+    }
+
+    /// Returns the maximum number of items the sack can hold, if it was created
+    /// with [`Sack::bounded`].
+    pub fn capacity(&self) -> Option<usize> {
+        (self.capacity != usize::MAX).then_some(self.capacity)
+    }
+
+    /// Checks if the sack is empty.
+    ///
+    /// This operation is lock-free.
+    pub fn is_empty(&self) -> bool {
+        untag(self.head.load(Ordering::Acquire)).is_null()
+    }
+
+    /// Closes the sack, so that no more items can be added.
+    ///
+    /// Items that are already in the sack stay there until they are drained.
+    /// Returns `false` if the sack was already closed.
+    ///
+    /// This operation is lock-free.
+    pub fn close(&self) -> bool {
+        let closed = self.head.fetch_or(CLOSED, Ordering::AcqRel).addr() & CLOSED == 0;
+        #[cfg(feature = "std")]
+        self.unpark_sleepers();
+        closed
+    }
+
+    /// Closes the sack and drains all items from it in one atomic step.
+    ///
+    /// Every item that was successfully added is either yielded by the returned
+    /// iterator or was drained before, and every later attempt to add an item
+    /// fails. The exception are items that a concurrent [`Sack::drain_oldest`]
+    /// or [`Sack::drain_filter`] has detached and doesn't take: they are put back
+    /// into the closed sack once that call is done, so drain it again after such
+    /// calls have returned to be sure to get them.
+    ///
+    /// This operation is lock-free.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let sack = Sack::new();
+    /// sack.add(1);
+    ///
+    /// assert_eq!(sack.close_and_drain().collect::<Vec<_>>(), vec![1]);
+    /// assert!(sack.is_closed());
+    /// assert_eq!(sack.try_add(2), Err(2));
+    /// ```
+    pub fn close_and_drain(&self) -> Drain<T> {
+        let head = self
+            .head
+            .swap(ptr::without_provenance_mut(CLOSED), Ordering::AcqRel);
+        #[cfg(feature = "std")]
+        self.unpark_sleepers();
+        self.detached(untag(head), None)
+    }
+
+    /// Checks if the sack has been closed.
+    ///
+    /// This operation is lock-free.
+    pub fn is_closed(&self) -> bool {
+        self.head.load(Ordering::Acquire).addr() & CLOSED != 0
+    }
+
+    /// Returns the number of items in the sack.
+    ///
+    /// Unlike [`Sack::len`], this works for every sack. It uses the tracked
+    /// length if there is one and otherwise walks the entries, which is only
+    /// possible while no other thread can access the sack.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let mut sack = Sack::new();
+    /// sack.add_all(0..3);
+    ///
+    /// assert_eq!(sack.len(), None);
+    /// assert_eq!(sack.count(), 3);
+    /// ```
+    pub fn count(&mut self) -> usize {
+        match &self.len {
+            Some(len) => len.load(Ordering::Relaxed),
+            None => unsafe { Entry::count(untag(self.head.load(Ordering::Relaxed))) },
+        }
+    }
+
+    /// Returns an iterator over the items, from newest to oldest.
+    ///
+    /// This walks the entries directly instead of draining them, which is only
+    /// possible while no other thread can access the sack.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let mut sack = Sack::new();
+    /// sack.add_all(0..3);
+    ///
+    /// assert_eq!(sack.iter().len(), 3);
+    /// assert_eq!(sack.iter().copied().collect::<Vec<_>>(), vec![2, 1, 0]);
+    /// ```
+    pub fn iter(&mut self) -> Iter<'_, T> {
+        Iter {
+            len: self.count(),
+            head: untag(self.head.load(Ordering::Relaxed)),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns an iterator that allows modifying the items, from newest to oldest.
+    ///
+    /// See [`Sack::iter`] for more.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            len: self.count(),
+            head: untag(self.head.load(Ordering::Relaxed)),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a mutable reference to the item at `index`, counting from the
+    /// newest item, or `None` if the sack has fewer items.
+    ///
+    /// Like [`Sack::iter`], this needs exclusive access.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let mut sack = Sack::new();
+    /// sack.add_all(0..3);
+    ///
+    /// if let Some(item) = sack.get_mut(0) {
+    ///     *item = 10;
+    /// }
+    /// assert_eq!(sack.get_mut(3), None);
+    /// assert_eq!(sack.drain().collect::<Vec<_>>(), vec![10, 1, 0]);
+    /// ```
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.iter_mut().nth(index)
+    }
+
+    /// Keeps only the items for which `f` returns `true`, dropping the others.
+    ///
+    /// The items are visited from newest to oldest and the order of the kept
+    /// items doesn't change. Like [`Sack::iter`], this needs exclusive access.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    ///
+    /// let mut sack = Sack::with_len_tracking();
+    /// sack.add_all(0..6);
+    /// sack.retain(|&item| item % 2 == 0);
+    ///
+    /// assert_eq!(sack.len(), Some(3));
+    /// assert_eq!(sack.drain_fifo().collect::<Vec<_>>(), vec![0, 2, 4]);
+    /// ```
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        let mut prev: *mut Entry<T> = ptr::null_mut();
+        let mut current = untag(self.head.load(Ordering::Relaxed));
+        while !current.is_null() {
+            let next = unsafe { (*current).next.load(Ordering::Relaxed) };
+            if f(unsafe { &(*current).item }) {
+                prev = current;
+            } else {
+                // Unlink the entry first, so the sack stays intact if the item panics.
+                match unsafe { prev.as_ref() } {
+                    Some(prev) => prev.next.store(next, Ordering::Relaxed),
+                    None => {
+                        let closed = self.head.load(Ordering::Relaxed).addr() & CLOSED;
+                        self.head
+                            .store(next.map_addr(|addr| addr | closed), Ordering::Relaxed);
+                    }
+                }
+                self.track_removed(1);
+                self.track_consumed(1);
+                drop(unsafe { Box::from_raw(current) });
+            }
+            current = next;
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Sack<T> {
+    /// Drains all items from the sack, blocking the current thread until there
+    /// is at least one.
+    ///
+    /// The thread is parked while it waits, and unparked by whoever adds an item
+    /// to the empty sack. If the sack is [closed](Sack::close), this returns the
+    /// remaining items right away, which may be none. Like for
+    /// [`Sack::close_and_drain`], that misses items that a concurrent
+    /// [`Sack::drain_filter`] or [`Sack::drain_oldest`] puts back afterwards.
+    ///
+    /// This is only available with the `std` feature.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    /// use std::sync::Arc;
+    /// use std::thread;
+    ///
+    /// let sack = Arc::new(Sack::new());
+    ///
+    /// let producer = {
+    ///     let sack = Arc::clone(&sack);
+    ///     thread::spawn(move || sack.add(1))
+    /// };
+    ///
+    /// assert_eq!(sack.drain_blocking().collect::<Vec<_>>(), vec![1]);
+    /// producer.join().unwrap();
+    /// ```
+    pub fn drain_blocking(&self) -> Drain<T> {
+        self.drain_until(None)
+    }
+
+    /// Drains all items from the sack, blocking the current thread for at most
+    /// `timeout` until there is at least one.
+    ///
+    /// Works like [`Sack::drain_blocking`], but returns an empty iterator if no
+    /// item was added in time.
+    ///
+    /// This is only available with the `std` feature.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::Sack;
+    /// use std::time::Duration;
+    ///
+    /// let sack = Sack::<i32>::new();
+    /// assert_eq!(sack.drain_timeout(Duration::from_millis(10)).count(), 0);
+    ///
+    /// sack.add(1);
+    /// assert_eq!(sack.drain_timeout(Duration::from_millis(10)).count(), 1);
+    /// ```
+    pub fn drain_timeout(&self, timeout: Duration) -> Drain<T> {
+        self.drain_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Drains all items from the sack, parking until there is at least one or
+    /// `deadline` has passed.
+    fn drain_until(&self, deadline: Option<Instant>) -> Drain<T> {
+        let sleeper = Entry {
+            item: Sleeper {
+                thread: thread::current(),
+                woken: AtomicBool::new(true),
+            },
+            next: AtomicPtr::new(ptr::null_mut()),
+        };
+        let drain = loop {
+            let drain = self.drain();
+            if !drain.is_empty() {
+                break drain;
+            }
+            if self.is_closed() {
+                // No item can be added anymore, so this is the final batch.
+                break self.drain();
+            }
+
+            // Parking can return spuriously, so only register again once woken,
+            // which keeps the sleeper in the list at most once.
+            if sleeper.item.woken.load(Ordering::Acquire) {
+                sleeper.item.woken.store(false, Ordering::Relaxed);
+                let sleeper = ptr::from_ref(&sleeper).cast_mut();
+                let _ = unsafe { Entry::push(&self.sleepers, sleeper, sleeper) };
+            }
+            // Pairs with the fence in `unpark_sleepers`, so that either we see the
+            // new item or the producer sees us.
+            atomic::fence(Ordering::SeqCst);
+            if !self.is_empty() || self.is_closed() {
+                continue;
+            }
+
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break self.drain();
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        };
+
+        if !sleeper.item.woken.load(Ordering::Acquire) {
+            // The sleeper can't be unlinked on its own, so take out the whole list.
+            // This only causes the others a spurious wakeup. If someone else took
+            // it out first, they are about to wake us.
+            self.wake_sleepers();
+            while !sleeper.item.woken.load(Ordering::Acquire) {
+                hint::spin_loop();
+            }
+        }
+        drain
+    }
+
+    /// Unparks all threads waiting for the sack to become non-empty.
+    fn unpark_sleepers(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::Relaxed).is_null() {
+            return;
+        }
+        self.wake_sleepers();
+    }
+
+    /// Takes all sleepers out of the list and unparks them.
+    fn wake_sleepers(&self) {
+        let mut current = self.sleepers.swap(ptr::null_mut(), Ordering::Acquire);
+        while !current.is_null() {
+            let (next, thread) = unsafe {
+                (
+                    (*current).next.load(Ordering::Relaxed),
+                    (*current).item.thread.clone(),
+                )
+            };
+            // The sleeper may be gone as soon as it is marked as woken.
+            unsafe { (*current).item.woken.store(true, Ordering::Release) };
+            thread.unpark();
+            current = next;
+        }
+    }
+}
+
+impl<T> Drop for Sack<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Drain::private(untag(self.head.load(Ordering::Relaxed))) });
+        unsafe { Entry::<T>::free_chain(self.spare.load(Ordering::Relaxed).cast()) };
+    }
+}
+
+impl<T> IntoIterator for Sack<T> {
+    type Item = T;
+    type IntoIter = Drain<T>;
+
+    /// Turns the sack into an iterator over its items, from newest to oldest.
+    fn into_iter(self) -> Drain<T> {
+        let len = self.len();
+        let head = untag(self.head.swap(ptr::null_mut(), Ordering::Relaxed));
+        // No one else can be reading the entries, so they can be freed right away.
+        Drain {
+            head,
+            len,
+            shared: ptr::null_mut(),
+            used: None,
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Sack<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+impl<T> Extend<T> for &Sack<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.add_all(iter);
+    }
+}
+
+/// A thread blocked in [`Sack::drain_blocking`] or [`Sack::drain_timeout`].
+#[cfg(feature = "std")]
+struct Sleeper {
+    thread: Thread,
+    /// Whether the sleeper is out of [`Sack::sleepers`], either because it
+    /// hasn't registered or because it was taken out and unparked.
+    woken: AtomicBool,
+}
+
+/// A draining iterator for [`Sack<T>`].
+///
+/// This struct is created by [`Sack<T>::drain`]. See its documentation for more.
+///
+/// Its [`len`](ExactSizeIterator::len) walks the remaining items unless the sack
+/// [tracks its length](Sack::with_len_tracking).
+pub struct Drain<T> {
+    /// The next entry to yield.
+    head: *mut Entry<T>,
+    /// The number of items left, if it was known when the chain was detached.
+    /// Otherwise they are counted when asked for.
+    len: Option<usize>,
+    /// The whole detached chain if it has to be retired instead of freed, since a
+    /// concurrent [`Sack::pop`] on the same sack might still be reading it. Null
+    /// otherwise.
+    shared: *mut Entry<T>,
+    /// The used capacity of the bounded sack the items came from, which is
+    /// released as they are yielded.
+    used: Option<Arc<AtomicUsize>>,
+}
+
+unsafe impl<T: Send> Send for Drain<T> {}
+unsafe impl<T: Sync> Sync for Drain<T> {}
+
+impl<T> Drain<T> {
+    /// Creates a draining iterator that frees the entries of a chain as it goes.
+    ///
+    /// # Safety
+    ///
+    /// `head` must be null or the head of a chain that isn't reachable by anyone else.
+    unsafe fn private(head: *mut Entry<T>) -> Self {
+        Self {
+            head,
+            len: None,
+            shared: ptr::null_mut(),
+            used: None,
+        }
+    }
+
+    /// Takes `count` items out of the remaining ones, releasing their capacity.
+    fn consumed(&mut self, count: usize) {
+        if let Some(len) = &mut self.len {
+            *len -= count;
+        }
+        if let Some(used) = &self.used {
+            used.fetch_sub(count, Ordering::Relaxed);
+        }
+    }
+
+    /// Checks if there are no items left.
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// Reverses the remaining items in place.
+    fn reverse(&mut self) {
+        self.head = unsafe { Entry::reverse(self.head) }.0;
+        if !self.shared.is_null() {
+            self.shared = self.head;
+        }
+    }
+}
+impl<T> Iterator for Drain<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.head.is_null() {
+            return None;
+        }
+        self.consumed(1);
+        if self.shared.is_null() {
+            let entry = unsafe { Box::from_raw(self.head) };
+            self.head = entry.next.load(Ordering::Relaxed);
+            Some(entry.item)
+        } else {
+            let entry = unsafe { &*self.head };
+            self.head = entry.next.load(Ordering::Relaxed);
+            Some(unsafe { ptr::read(&entry.item) })
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self
+            .len
+            .unwrap_or_else(|| unsafe { Entry::count(self.head) });
+        (len, Some(len))
+    }
+}
+impl<T> ExactSizeIterator for Drain<T> {}
+impl<T> Drop for Drain<T> {
+    fn drop(&mut self) {
+        self.by_ref().for_each(drop);
+        if !self.shared.is_null() {
+            unsafe { epoch::retire(self.shared.cast(), Entry::<T>::free_chain) };
+        }
+    }
+}
+
+/// A draining iterator for [`Sack<T>`] that keeps the entries for reuse.
+///
+/// This struct is created by [`Sack<T>::drain_recycle`]. See its documentation for more.
+pub struct Recycle<'a, T> {
+    sack: &'a Sack<T>,
+    drain: Drain<T>,
+    /// The first entry of the detached chain.
+    first: *mut Entry<T>,
+    /// The last entry whose item has been yielded.
+    last: *mut Entry<T>,
+}
+
+unsafe impl<T: Send> Send for Recycle<'_, T> {}
+unsafe impl<T: Sync> Sync for Recycle<'_, T> {}
+
+impl<T> Iterator for Recycle<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.drain.shared.is_null() {
+            // Others might still be reading the entries, so they can't be reused.
+            return self.drain.next();
+        }
+        let entry = unsafe { self.drain.head.as_ref() }?;
+        self.last = self.drain.head;
+        self.drain.head = entry.next.load(Ordering::Relaxed);
+        self.drain.consumed(1);
+        Some(unsafe { ptr::read(&entry.item) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.drain.size_hint()
+    }
+}
+impl<T> ExactSizeIterator for Recycle<'_, T> {}
+impl<T> Drop for Recycle<'_, T> {
+    fn drop(&mut self) {
+        self.by_ref().for_each(drop);
+        if self.drain.shared.is_null() && !self.first.is_null() {
+            let _ = unsafe { Entry::push(&self.sack.spare, self.first, self.last) };
+        }
+    }
+}
+
+/// The state of [`Sack::drain_filter`], which puts the items that weren't
+/// taken back when dropped.
+struct Filter<'a, T> {
+    sack: &'a Sack<T>,
+    /// The items that haven't been looked at yet.
+    rest: Drain<T>,
+    /// The private entry of the item that is being looked at, if any.
+    current: *mut Entry<T>,
+    /// The first and last entry of the private chain of items to put back.
+    kept: (*mut Entry<T>, *mut Entry<T>),
+}
+
+impl<T> Filter<'_, T> {
+    /// Takes the next item out of `rest` into a private entry.
+    fn next_entry(&mut self) -> Option<*mut Entry<T>> {
+        self.current = if self.rest.shared.is_null() {
+            let entry = self.rest.head;
+            self.rest.head = unsafe { entry.as_ref() }?.next.load(Ordering::Relaxed);
+            self.rest.consumed(1);
+            entry
+        } else {
+            // See `Sack::split_front`.
+            let item = self.rest.next()?;
+            self.sack.alloc(item)
+        };
+        Some(self.current)
+    }
+
+    /// Appends the current entry to the chain of items to put back.
+    fn keep(&mut self) {
+        let entry = mem::replace(&mut self.current, ptr::null_mut());
+        unsafe { (*entry).next.store(ptr::null_mut(), Ordering::Relaxed) };
+        match unsafe { self.kept.1.as_ref() } {
+            Some(last) => last.next.store(entry, Ordering::Relaxed),
+            None => self.kept.0 = entry,
+        }
+        self.kept.1 = entry;
+    }
+}
+
+impl<T> Drop for Filter<'_, T> {
+    fn drop(&mut self) {
+        // Only if the predicate panicked.
+        if !self.current.is_null() {
+            self.keep();
+        }
+        let len = self.rest.len();
+        if len > 0 {
+            let (first, last) = self.sack.split_front(&mut self.rest, len);
+            match unsafe { self.kept.1.as_ref() } {
+                Some(kept) => kept.next.store(first, Ordering::Relaxed),
+                None => self.kept.0 = first,
+            }
+            self.kept.1 = last;
+        }
+
+        if !self.kept.0.is_null() {
+            unsafe { self.sack.restore(self.kept.0, self.kept.1) };
+        }
+    }
+}
+
+/// An iterator over the items of a [`Sack<T>`].
+///
+/// This struct is created by [`Sack<T>::iter`]. See its documentation for more.
+pub struct Iter<'a, T> {
+    head: *const Entry<T>,
+    /// The number of items left.
+    len: usize,
+    _marker: PhantomData<&'a T>,
+}
+
+unsafe impl<T: Sync> Send for Iter<'_, T> {}
+unsafe impl<T: Sync> Sync for Iter<'_, T> {}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = unsafe { self.head.as_ref() }?;
+        self.head = entry.next.load(Ordering::Relaxed);
+        self.len -= 1;
+        Some(&entry.item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+/// An iterator over mutable references to the items of a [`Sack<T>`].
+///
+/// This struct is created by [`Sack<T>::iter_mut`]. See its documentation for more.
+pub struct IterMut<'a, T> {
+    head: *mut Entry<T>,
+    /// The number of items left.
+    len: usize,
+    _marker: PhantomData<&'a mut T>,
+}
+
+unsafe impl<T: Send> Send for IterMut<'_, T> {}
+unsafe impl<T: Sync> Sync for IterMut<'_, T> {}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = unsafe { self.head.as_mut() }?;
+        self.head = entry.next.load(Ordering::Relaxed);
+        self.len -= 1;
+        Some(&mut entry.item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+impl<T> ExactSizeIterator for IterMut<'_, T> {}
+
+/// A reader of the entries of a [`Sack<T>`], created by [`Sack::read`].
+///
+/// Chains detached while a sack has readers are shared (see [`Drain`]), while
+/// other sacks aren't affected.
+struct ReadGuard<'a> {
+    readers: &'a AtomicUsize,
+    epoch: epoch::Guard,
+}
+
+impl Drop for ReadGuard<'_> {
+    fn drop(&mut self) {
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// An iterator over copies of the items of a [`Sack<T>`] that doesn't take them out.
+///
+/// This struct is created by [`Sack<T>::iter_shared`]. See its documentation for more.
+pub struct SharedIter<'a, T> {
+    head: *const Entry<T>,
+    /// Keeps the entries reachable from `head` alive.
+    _guard: ReadGuard<'a>,
+    _marker: PhantomData<&'a Sack<T>>,
+}
+
+unsafe impl<T: Sync> Send for SharedIter<'_, T> {}
+unsafe impl<T: Sync> Sync for SharedIter<'_, T> {}
+
+impl<T: Copy> Iterator for SharedIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // SAFETY: see `Sack::any`.
+        let entry = unsafe { self.head.as_ref() }?;
+        self.head = entry.next.load(Ordering::Relaxed);
+        Some(entry.item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+        },
+        thread, vec,
+        vec::Vec,
+    };
+
+    use super::*;
+    use crate::test_util::spawn_popper;
+
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_sack_add_drain() {
+        let sack = Sack::new();
+        sack.add(1);
+        sack.add(2);
+        sack.add(3);
+
+        let mut drained: Vec<_> = sack.drain().collect();
+        drained.sort();
+        assert_eq!(drained, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_sack_is_empty() {
+        let sack = Sack::new();
+        assert!(sack.is_empty());
+        sack.add(1);
+        assert!(!sack.is_empty());
+        let _ = sack.drain();
+        assert!(sack.is_empty());
+    }
+
+    #[test]
+    fn test_sack_add_all() {
+        let mut sack = &Sack::new();
+        sack.add_all([]);
+        assert!(sack.is_empty());
+
+        sack.add(0);
+        sack.add_all([1, 2]);
+        sack.extend(3..5);
+        sack.add(5);
+        assert_eq!(sack.drain().collect::<Vec<_>>(), vec![5, 4, 3, 2, 1, 0]);
+
+        // Items taken from a panicking iterator are dropped, not leaked or added.
+        let count = Arc::new(AtomicUsize::new(0));
+        let sack = Sack::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            sack.add_all((0..3).map(|i| {
+                assert!(i < 2);
+                DropCounter(count.clone())
+            }));
+        }));
+        assert!(result.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert!(sack.is_empty());
+    }
+
+    #[test]
+    fn test_sack_len() {
+        let sack = Sack::with_len_tracking();
+        assert_eq!(sack.len(), Some(0));
+        sack.add(0);
+        sack.add_all(1..5);
+        assert_eq!(sack.len(), Some(5));
+        assert_eq!(sack.pop(), Some(4));
+        assert_eq!(sack.len(), Some(4));
+
+        let mut drain = sack.drain();
+        assert_eq!(sack.len(), Some(0));
+        assert_eq!(drain.len(), 4);
+        drain.next();
+        assert_eq!(drain.size_hint(), (3, Some(3)));
+        assert_eq!(drain.collect::<Vec<_>>(), vec![2, 1, 0]);
+
+        sack.add_all(0..3);
+        assert_eq!(sack.drain_fifo().len(), 3);
+        sack.add_all(0..3);
+        assert_eq!(sack.drain_recycle().len(), 3);
+        sack.add(0);
+        sack.close();
+        sack.add(1);
+        assert_eq!(sack.len(), Some(1));
+        assert_eq!(sack.close_and_drain().len(), 1);
+        assert_eq!(sack.len(), Some(0));
+        assert_eq!(Sack::<i32>::new().len(), None);
+
+        // Iterators know their length without tracking, also after partial takes.
+        let mut sack = Sack::new();
+        sack.add_all(0..6);
+        assert_eq!(sack.count(), 6);
+        assert_eq!(sack.iter().len(), 6);
+        let mut iter = sack.iter_mut();
+        iter.next();
+        assert_eq!(iter.len(), 5);
+        assert_eq!(sack.drain_oldest(2).len(), 2);
+        assert_eq!(sack.drain_filter(|&mut item| item % 2 == 0).len(), 2);
+        let guard = sack.read();
+        let mut recycle = sack.drain_recycle();
+        recycle.next();
+        assert_eq!(recycle.len(), 1);
+        drop((recycle, guard));
+        // Untracked drains only count their items when asked.
+        sack.add_all(0..3);
+        let mut drain = sack.drain();
+        assert_eq!(drain.len, None);
+        drain.next();
+        assert_eq!(drain.len(), 2);
+        drop(drain);
+        sack.add_all(0..3);
+        assert_eq!(sack.into_iter().len(), 3);
+    }
+
+    #[test]
+    fn test_sack_concurrent_len() {
+        let sack = Arc::new(Sack::with_len_tracking());
+        let mut handles = vec![];
+
+        for _ in 0..4 {
+            let sack = Arc::clone(&sack);
+            handles.push(thread::spawn(move || {
+                let mut taken = 0;
+                for i in 0..1000 {
+                    sack.add(i);
+                    if i % 3 == 0 {
+                        taken += sack.pop().is_some() as usize;
+                    }
+                    if i % 10 == 0 {
+                        taken += sack.drain().len();
+                    }
+                }
+                taken
+            }));
+        }
+
+        let taken: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(sack.len(), Some(4000 - taken));
+        assert_eq!(sack.drain().len(), 4000 - taken);
+    }
+
+    #[test]
+    fn test_sack_bounded() {
+        let sack = Sack::bounded(3);
+        assert_eq!(sack.capacity(), Some(3));
+        assert_eq!(Sack::<i32>::new().capacity(), None);
+
+        sack.add_all([0, 1]);
+        assert_eq!(sack.try_add(2), Ok(()));
+        assert_eq!(sack.try_add(3), Err(3));
+        assert_eq!(sack.len(), Some(3));
+
+        assert_eq!(sack.pop(), Some(2));
+        // Batches are all or nothing.
+        sack.add_all([3, 4]);
+        assert_eq!(sack.len(), Some(2));
+        sack.add(3);
+
+        // Capacity is only released as the drain yields items.
+        let mut drain = sack.drain_fifo();
+        assert_eq!(sack.len(), Some(0));
+        assert_eq!(sack.try_add(5), Err(5));
+        assert_eq!(drain.next(), Some(0));
+        assert_eq!(sack.try_add(5), Ok(()));
+        let rejected = sack.try_add_all([6, 7]).unwrap_err();
+        assert_eq!(rejected.collect::<Vec<_>>(), vec![6, 7]);
+        drop(drain);
+        assert!(sack.try_add_all([6, 7]).is_ok());
+        assert_eq!(sack.try_add(8), Err(8));
+
+        // Taking the items out of a drain in another way releases them as well.
+        let other = Sack::new();
+        assert!(other.append(sack.drain_fifo()).is_ok());
+        assert!(sack.try_add_all([8, 9, 10]).is_ok());
+        let mut sack = sack;
+        sack.retain(|&item| item != 9);
+        let _ = sack.drain_filter(|&mut item| item == 8);
+        assert_eq!(sack.drain_oldest(1).count(), 1);
+        assert_eq!(sack.len(), Some(0));
+        assert!(sack.try_add_all(0..3).is_ok());
+        assert_eq!(other.drain_fifo().collect::<Vec<_>>(), vec![5, 6, 7]);
+    }
+
+    #[test]
+    fn test_sack_concurrent_bounded() {
+        let sack = Arc::new(Sack::bounded(16));
+        let mut handles = vec![];
+
+        for i in 0..4 {
+            let sack = Arc::clone(&sack);
+            handles.push(thread::spawn(move || {
+                for j in 0..1000 {
+                    let mut item = i * 1000 + j;
+                    while let Err(rejected) = sack.try_add(item) {
+                        item = rejected;
+                        thread::yield_now();
+                    }
+                }
+            }));
+        }
+
+        let mut drained = Vec::new();
+        while drained.len() < 4000 {
+            let drain = sack.drain();
+            assert!(drain.len() <= 16);
+            drained.extend(drain);
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        drained.sort();
+        assert_eq!(drained, (0..4000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sack_close() {
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let sack = Sack::new();
+        assert!(!sack.is_closed());
+        sack.add(DropCounter(count.clone()));
+        sack.add(DropCounter(count.clone()));
+        assert!(sack.close());
+        assert!(!sack.close());
+        assert!(sack.is_closed());
+        assert!(!sack.is_empty());
+
+        // Adding fails, but the items in the sack can still be taken.
+        assert!(sack.try_add(DropCounter(count.clone())).is_err());
+        sack.add(DropCounter(count.clone()));
+        sack.add_all([DropCounter(count.clone()), DropCounter(count.clone())]);
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+        drop(sack.pop());
+        assert!(sack.is_closed());
+        assert_eq!(sack.drain_fifo().count(), 1);
+        assert!(sack.is_closed());
+        assert!(sack.is_empty());
+        assert_eq!(count.load(Ordering::SeqCst), 6);
+
+        let sack = Sack::new();
+        sack.add(1);
+        sack.add(2);
+        assert_eq!(sack.close_and_drain().collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(sack.try_add(3), Err(3));
+        assert_eq!(sack.drain().count(), 0);
+        assert_eq!(sack.pop(), None);
+    }
+
+    #[test]
+    fn test_sack_concurrent_close() {
+        let sack = Arc::new(Sack::new());
+        let mut handles = vec![];
+
+        for i in 0..4 {
+            let sack = Arc::clone(&sack);
+            handles.push(thread::spawn(move || {
+                let mut added = Vec::new();
+                for j in 0..1000 {
+                    if sack.try_add(i * 1000 + j).is_ok() {
+                        added.push(i * 1000 + j);
+                    }
+                }
+                added
+            }));
+        }
+
+        let mut drained: Vec<_> = sack.drain().collect();
+        drained.extend(sack.close_and_drain());
+        let mut added: Vec<_> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+
+        drained.sort();
+        added.sort();
+        assert_eq!(drained, added);
+        assert!(sack.is_empty());
+    }
+
+    #[test]
+    fn test_sack_drain_fifo() {
+        let sack = Sack::new();
+        assert_eq!(sack.drain_fifo().next(), None);
+        for i in 0..10 {
+            sack.add(i);
+        }
+        assert_eq!(
+            sack.drain_fifo().collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+        assert!(sack.is_empty());
+
+        sack.add(10);
+        sack.add(11);
+        let mut drain = sack.drain_fifo();
+        assert_eq!(drain.next(), Some(10));
+        sack.add(12);
+        assert_eq!(drain.next(), Some(11));
+        assert_eq!(drain.next(), None);
+        assert_eq!(sack.pop(), Some(12));
+    }
+
+    #[test]
+    fn test_sack_drain_oldest() {
+        let sack = Sack::with_len_tracking();
+        assert_eq!(sack.drain_oldest(1).count(), 0);
+
+        sack.add_all(0..5);
+        assert_eq!(sack.drain_oldest(0).count(), 0);
+        assert_eq!(sack.drain_oldest(2).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(sack.len(), Some(3));
+        sack.close();
+        assert_eq!(sack.drain_oldest(1).collect::<Vec<_>>(), vec![2]);
+        assert!(sack.is_closed());
+        assert_eq!(sack.drain_oldest(5).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(sack.len(), Some(0));
+
+        // Entries that a reader might be looking at are copied, not put back.
+        let sack = Sack::new();
+        sack.add_all(0..5);
+        let guard = sack.read();
+        let head = sack.head.load(Ordering::Relaxed);
+        assert_eq!(sack.drain_oldest(2).collect::<Vec<_>>(), vec![0, 1]);
+        assert_ne!(sack.head.load(Ordering::Relaxed), head);
+        drop(guard);
+        assert_eq!(sack.drain_fifo().collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        // Readers of other sacks don't matter.
+        sack.add_all(0..5);
+        let other = Sack::<i32>::new();
+        let guard = other.read();
+        let head = sack.head.load(Ordering::Relaxed);
+        assert_eq!(sack.drain_oldest(2).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(sack.head.load(Ordering::Relaxed), head);
+        drop(guard);
+        assert_eq!(sack.drain_fifo().collect::<Vec<_>>(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_sack_append() {
+        let local = Sack::with_len_tracking();
+        local.add_all(0..3);
+        let newest = untag(local.head.load(Ordering::Relaxed));
+
+        let global = Sack::with_len_tracking();
+        global.add(10);
+        assert!(global.append_sack(&local).is_ok());
+        assert_eq!(local.len(), Some(0));
+        assert_eq!(global.len(), Some(4));
+        // The entries are reused, and the newest one of `local` is the newest here too.
+        assert_eq!(untag(global.head.load(Ordering::Relaxed)), newest);
+
+        local.add_all(20..23);
+        let mut drain = local.drain();
+        drain.next();
+        assert!(global.append(drain).is_ok());
+        assert!(global.append(local.drain()).is_ok());
+        assert_eq!(
+            global.drain_fifo().collect::<Vec<_>>(),
+            vec![10, 0, 1, 2, 21, 20]
+        );
+
+        // Rejected items are handed back in their order, still reusing the entries.
+        let bounded = Sack::bounded(2);
+        local.add_all(0..3);
+        let rejected = bounded.append(local.drain()).unwrap_err();
+        assert!(bounded.is_empty());
+        assert_eq!(bounded.len(), Some(0));
+        assert_eq!(rejected.len(), 3);
+        assert_eq!(rejected.collect::<Vec<_>>(), vec![2, 1, 0]);
+
+        local.add_all(0..2);
+        bounded.close();
+        let rejected = bounded.append_sack(&local).unwrap_err();
+        assert!(bounded.is_empty());
+        assert!(local.is_empty());
+        assert!(local.append(rejected).is_ok());
+        assert_eq!(local.drain_fifo().collect::<Vec<_>>(), vec![0, 1]);
+
+        // The source's capacity is only released once the rejected items are taken.
+        let source = Sack::bounded(2);
+        source.add_all(0..2);
+        let rejected = bounded.append(source.drain_fifo()).unwrap_err();
+        assert_eq!(source.try_add(2), Err(2));
+        drop(rejected);
+        assert_eq!(source.try_add(2), Ok(()));
+
+        // A shared drain is moved into new entries, and handed back as well.
+        local.add_all(0..2);
+        let guard = local.read();
+        let rejected = bounded.append(local.drain_fifo()).unwrap_err();
+        drop(guard);
+        assert_eq!(rejected.collect::<Vec<_>>(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_sack_concurrent_append() {
+        let global = Sack::with_len_tracking();
+        let done = AtomicBool::new(false);
+
+        let mut drained = thread::scope(|s| {
+            for t in 0..4 {
+                let global = &global;
+                s.spawn(move || {
+                    let local = Sack::new();
+                    for i in 0..1000 {
+                        local.add(t * 1000 + i);
+                        if i % 10 == 9 {
+                            assert!(global.append_sack(&local).is_ok());
+                        }
+                    }
+                });
+            }
+            let popper = spawn_popper(s, || global.pop(), &done);
+            let mut drained = Vec::new();
+            for _ in 0..100 {
+                let batch: Vec<_> = global.drain_fifo().collect();
+                for t in 0..4 {
+                    let range = t * 1000..(t + 1) * 1000;
+                    let items: Vec<_> = batch.iter().filter(|&&i| range.contains(&i)).collect();
+                    assert!(items.is_sorted());
+                }
+                drained.extend(batch);
+            }
+            done.store(true, Ordering::Relaxed);
+            drained.extend(popper.join().unwrap());
+            drained
+        });
+
+        drained.extend(global.drain());
+        drained.sort();
+        assert_eq!(drained, (0..4000).collect::<Vec<_>>());
+        assert_eq!(global.len(), Some(0));
+    }
+
+    #[test]
+    fn test_sack_drain_filter() {
+        let sack = Sack::with_len_tracking();
+        sack.add_all(0..10);
+        let evens: Vec<_> = sack.drain_filter(|item| *item % 2 == 0).collect();
+        assert_eq!(evens, vec![0, 2, 4, 6, 8]);
+        assert_eq!(sack.len(), Some(5));
+        assert!(sack.drain_filter(|_| false).next().is_none());
+
+        sack.close();
+        assert_eq!(
+            sack.drain_filter(|item| *item == 9).collect::<Vec<_>>(),
+            vec![9]
+        );
+        assert!(sack.is_closed());
+        assert_eq!(sack.drain_fifo().collect::<Vec<_>>(), vec![1, 3, 5, 7]);
+
+        // Items that are kept while the sack is closed end up in it afterwards.
+        let sack = Sack::new();
+        sack.add(1);
+        let mut closed = None;
+        let taken = sack.drain_filter(|_| {
+            closed = Some(sack.close_and_drain().count());
+            false
+        });
+        assert!(taken.is_empty());
+        assert_eq!(closed, Some(0));
+        assert!(sack.is_closed());
+        assert_eq!(sack.drain().collect::<Vec<_>>(), vec![1]);
+
+        // A panicking predicate puts back everything that didn't match.
+        let count = Arc::new(AtomicUsize::new(0));
+        let sack = Sack::with_len_tracking();
+        sack.add_all((0..6).map(|_| DropCounter(count.clone())));
+        let mut seen = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            sack.drain_filter(|_| {
+                seen += 1;
+                assert!(seen < 4);
+                seen == 1
+            })
+            .for_each(drop);
+        }));
+        assert!(result.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(sack.len(), Some(5));
+        assert_eq!(sack.drain().count(), 5);
+    }
+
+    #[test]
+    fn test_sack_concurrent_drain_filter() {
+        let sack = Sack::new();
+        let done = AtomicBool::new(false);
+
+        let (mut taken, popped) = thread::scope(|s| {
+            for t in 0..4 {
+                let sack = &sack;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        sack.add(t * 1000 + i);
+                    }
+                });
+            }
+            let popper = spawn_popper(s, || sack.pop(), &done);
+            let mut taken = Vec::new();
+            for _ in 0..100 {
+                let before = taken.len();
+                taken.extend(sack.drain_filter(|item| *item % 2 == 0));
+                // Matches come out in the order they were added per producer.
+                let batch = &taken[before..];
+                for t in 0..4 {
+                    let range = t * 1000..(t + 1) * 1000;
+                    let items: Vec<_> = batch.iter().filter(|&&i| range.contains(&i)).collect();
+                    assert!(items.is_sorted());
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+            (taken, popper.join().unwrap())
+        });
+
+        taken.extend(popped.into_iter().filter(|item| item % 2 == 0));
+        taken.extend(sack.drain_filter(|item| *item % 2 == 0));
+        taken.sort();
+        assert_eq!(taken, (0..4000).step_by(2).collect::<Vec<_>>());
+        let mut rest: Vec<_> = sack.drain().collect();
+        rest.sort();
+        assert!(rest.iter().all(|item| item % 2 == 1));
+    }
+
+    #[test]
+    fn test_sack_concurrent_drain_oldest() {
+        let sack = Arc::new(Sack::with_len_tracking());
+        let mut handles = vec![];
+
+        for i in 0..4 {
+            let sack = Arc::clone(&sack);
+            handles.push(thread::spawn(move || {
+                let mut drained = Vec::new();
+                for j in 0..1000 {
+                    sack.add(i * 1000 + j);
+                    if j % 2 == 0 {
+                        drained.extend(sack.drain_oldest(1));
+                    }
+                    if j % 100 == 0 {
+                        sack.pop().into_iter().for_each(|item| drained.push(item));
+                    }
+                }
+                drained
+            }));
+        }
+
+        let mut drained: Vec<_> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        assert_eq!(sack.len(), Some(4000 - drained.len()));
+        drained.extend(sack.drain());
+        drained.sort();
+        assert_eq!(drained, (0..4000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sack_pop() {
+        let sack = Sack::new();
+        sack.add(1);
+        sack.add(2);
+        assert_eq!(sack.pop(), Some(2));
+        sack.add(3);
+        assert_eq!(sack.pop(), Some(3));
+        assert_eq!(sack.pop(), Some(1));
+        assert_eq!(sack.pop(), None);
+        assert!(sack.is_empty());
+    }
+
+    #[test]
+    fn test_sack_concurrent_pop() {
+        let sack = Arc::new(Sack::new());
+        let popped = Arc::new(Sack::new());
+        let mut handles = vec![];
+
+        for i in 0..4 {
+            let sack = Arc::clone(&sack);
+            handles.push(thread::spawn(move || {
+                for j in 0..1000 {
+                    sack.add(i * 1000 + j);
+                }
+            }));
+        }
+        for _ in 0..4 {
+            let sack = Arc::clone(&sack);
+            let popped = Arc::clone(&popped);
+            handles.push(thread::spawn(move || {
+                for _ in 0..1000 {
+                    if let Some(item) = sack.pop() {
+                        popped.add(item);
+                    }
+                    if let Some(item) = sack.pop() {
+                        popped.add(item);
+                    }
+                    for item in sack.drain() {
+                        popped.add(item);
+                    }
+                    for item in sack.drain_recycle() {
+                        popped.add(item);
+                    }
+                }
+            }));
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut items: Vec<_> = popped.drain().chain(sack.drain()).collect();
+        items.sort();
+        assert_eq!(items, (0..4000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sack_drop() {
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let sack = Sack::new();
+        for _ in 0..10 {
+            sack.add(DropCounter(count.clone()));
+        }
+        drop(sack);
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+
+        let sack = Sack::new();
+        sack.add_all((0..10).map(|_| DropCounter(count.clone())));
+        drop(sack.pop());
+        let mut drain = sack.drain();
+        drop(drain.next());
+        sack.add(DropCounter(count.clone()));
+        drop(sack);
+        assert_eq!(count.load(Ordering::SeqCst), 13);
+        drop(drain);
+        assert_eq!(count.load(Ordering::SeqCst), 21);
+    }
+
+    #[test]
+    fn test_sack_exclusive() {
+        let mut sack = Sack::with_len_tracking();
+        sack.add_all(0..6);
+        assert_eq!(sack.iter().len(), 6);
+        assert_eq!(sack.count(), 6);
+        assert_eq!(sack.get_mut(5), Some(&mut 0));
+        assert_eq!(sack.get_mut(6), None);
+
+        for item in &mut sack {
+            *item *= 10;
+        }
+        sack.retain(|&item| item != 50 && item != 20);
+        assert_eq!(
+            sack.iter().copied().collect::<Vec<_>>(),
+            vec![40, 30, 10, 0]
+        );
+        assert_eq!(sack.len(), Some(4));
+
+        // The closed tag on the head survives removing the newest item.
+        sack.close();
+        sack.retain(|&item| item != 40);
+        assert!(sack.is_closed());
+        assert_eq!(sack.into_iter().collect::<Vec<_>>(), vec![30, 10, 0]);
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut sack = Sack::new();
+        sack.add_all((0..10).map(|_| DropCounter(count.clone())));
+        let mut keep = false;
+        sack.retain(|_| {
+            keep = !keep;
+            keep
+        });
+        assert_eq!(count.load(Ordering::SeqCst), 5);
+        let mut iter = sack.into_iter();
+        drop(iter.next());
+        assert_eq!(count.load(Ordering::SeqCst), 6);
+        drop(iter);
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_sack_concurrent_iter_shared() {
+        let sack = Sack::new();
+        let done = AtomicBool::new(false);
+
+        let drained: usize = thread::scope(|s| {
+            for t in 0..4 {
+                let sack = &sack;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        sack.add(t * 1000 + i);
+                    }
+                });
+            }
+            let drainer = s.spawn(|| {
+                let mut drained = 0;
+                while !done.load(Ordering::Relaxed) {
+                    drained += sack.drain_oldest(8).count() + sack.drain_fifo().count();
+                }
+                drained
+            });
+            for _ in 0..100 {
+                assert!(sack.iter_shared().all(|item| item < 4000));
+                sack.for_each_ref(|&item| assert!(item < 4000));
+            }
+            done.store(true, Ordering::Relaxed);
+            drainer.join().unwrap()
+        });
+
+        let rest: Vec<_> = sack.iter_shared().collect();
+        let mut seen = Vec::new();
+        sack.for_each_ref(|&item| seen.push(item));
+        assert_eq!(seen, rest);
+        assert_eq!(drained + rest.len(), 4000);
+    }
+
+    #[test]
+    fn test_sack_drain_recycle() {
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let sack = Sack::new();
+        sack.add_all((0..4).map(|_| DropCounter(count.clone())));
+        let mut recycle = sack.drain_recycle();
+        drop(recycle.next());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        drop(recycle);
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+
+        // All four spare entries are reused, two of them by one batch.
+        sack.add(DropCounter(count.clone()));
+        sack.add_all([DropCounter(count.clone()), DropCounter(count.clone())]);
+        sack.add(DropCounter(count.clone()));
+        assert!(sack.spare.load(Ordering::Relaxed).is_null());
+        assert_eq!(sack.drain_recycle().count(), 4);
+        assert!(!sack.spare.load(Ordering::Relaxed).is_null());
+
+        sack.add(DropCounter(count.clone()));
+        drop(sack);
+        assert_eq!(count.load(Ordering::SeqCst), 9);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sack_drain_blocking() {
+        let sack = Arc::new(Sack::new());
+        let consumer = {
+            let sack = Arc::clone(&sack);
+            thread::spawn(move || {
+                let mut drained = Vec::new();
+                while drained.len() < 100 {
+                    let drain = sack.drain_blocking();
+                    assert!(!drain.is_empty());
+                    drained.extend(drain);
+                }
+                drained
+            })
+        };
+
+        for i in 0..100 {
+            sack.add(i);
+            if i % 10 == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        let mut drained = consumer.join().unwrap();
+        drained.sort();
+        assert_eq!(drained, (0..100).collect::<Vec<_>>());
+
+        let consumer = {
+            let sack = Arc::clone(&sack);
+            thread::spawn(move || sack.drain_blocking().count())
+        };
+        thread::sleep(Duration::from_millis(10));
+        sack.close();
+        assert_eq!(consumer.join().unwrap(), 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sack_restore_wakes_sleepers() {
+        let sack = Sack::new();
+        sack.add(1);
+        let head = sack.detach();
+        thread::scope(|s| {
+            // The consumer blocks while the item is detached, like during `drain_oldest`.
+            let consumer = s.spawn(|| sack.drain_blocking().collect::<Vec<_>>());
+            while sack.sleepers.load(Ordering::Relaxed).is_null() {
+                thread::yield_now();
+            }
+            unsafe { sack.restore(head, head) };
+            assert_eq!(consumer.join().unwrap(), vec![1]);
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sack_drain_filter_blocking() {
+        let sack = Sack::new();
+        sack.add(1);
+        thread::scope(|s| {
+            // The consumer blocks while the kept item is detached.
+            let mut consumer = None;
+            let taken = sack.drain_filter(|_| {
+                consumer = Some(s.spawn(|| sack.drain_blocking().collect::<Vec<_>>()));
+                while sack.sleepers.load(Ordering::Relaxed).is_null() {
+                    thread::yield_now();
+                }
+                false
+            });
+            assert!(taken.is_empty());
+            assert_eq!(consumer.unwrap().join().unwrap(), vec![1]);
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sack_drain_timeout() {
+        let sack = Arc::new(Sack::new());
+        let start = Instant::now();
+        assert!(sack.drain_timeout(Duration::from_millis(20)).is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let producer = {
+            let sack = Arc::clone(&sack);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                sack.add(1);
+            })
+        };
+        let drained: Vec<_> = sack.drain_timeout(Duration::from_secs(60)).collect();
+        assert_eq!(drained, vec![1]);
+        assert!(start.elapsed() < Duration::from_secs(60));
+        producer.join().unwrap();
+
+        // Threads that time out take themselves out of the sleepers.
+        for _ in 0..100 {
+            assert!(sack.drain_timeout(Duration::ZERO).is_empty());
+            assert!(sack.sleepers.load(Ordering::Relaxed).is_null());
+        }
+
+        // Blocked threads that are woken by that register again.
+        let blocked = {
+            let sack = Arc::clone(&sack);
+            thread::spawn(move || sack.drain_blocking().collect::<Vec<_>>())
+        };
+        for _ in 0..100 {
+            assert!(sack.drain_timeout(Duration::ZERO).is_empty());
+            assert!(sack.drain_timeout(Duration::from_micros(100)).is_empty());
+        }
+        sack.add(2);
+        assert_eq!(blocked.join().unwrap(), vec![2]);
+        assert!(sack.sleepers.load(Ordering::Relaxed).is_null());
+    }
+
+    #[test]
+    fn test_sack_concurrent_add() {
+        let sack = Arc::new(Sack::new());
+        let mut handles = vec![];
+
+        for i in 0..10 {
+            let sack = Arc::clone(&sack);
+            handles.push(thread::spawn(move || {
+                for j in 0..100 {
+                    sack.add(i * 100 + j);
+                }
+            }));
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut drained: Vec<_> = sack.drain().collect();
+        assert_eq!(drained.len(), 1000);
+        drained.sort();
+        for (i, item) in drained.into_iter().enumerate() {
+            assert_eq!(item, i);
+        }
+    }
+}
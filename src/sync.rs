@@ -0,0 +1,109 @@
+//! The atomics used by the allocating types.
+//!
+//! When model checking with `RUSTFLAGS="--cfg loom"`, they are swapped for the
+//! ones from [loom](https://docs.rs/loom), which explores every interleaving and
+//! every outcome the memory orderings allow. See `tests/loom.rs`.
+
+/// Declares a function that is `const` unless model checking, since loom's
+/// atomics can't be created in constant expressions.
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis const fn $($rest:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])*
+        $vis const fn $($rest)*
+
+        #[cfg(loom)]
+        $(#[$attr])*
+        $vis fn $($rest)*
+    };
+}
+pub(crate) use const_fn;
+
+/// Drop-in replacements for `core::sync::atomic`.
+pub(crate) mod atomic {
+    #[cfg(all(feature = "waker", not(loom)))]
+    pub(crate) use core::sync::atomic::AtomicU8;
+    #[cfg(not(loom))]
+    pub(crate) use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering, fence};
+
+    #[cfg(loom)]
+    pub(crate) use self::loom_ptr::AtomicPtr;
+    #[cfg(all(feature = "waker", loom))]
+    pub(crate) use loom::sync::atomic::AtomicU8;
+    #[cfg(loom)]
+    pub(crate) use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering, fence};
+
+    #[cfg(loom)]
+    mod loom_ptr {
+        use super::Ordering;
+
+        /// Loom's `AtomicPtr`, with the operations on the address that it lacks.
+        #[derive(Debug)]
+        pub(crate) struct AtomicPtr<T>(loom::sync::atomic::AtomicPtr<T>);
+
+        impl<T> AtomicPtr<T> {
+            pub(crate) fn new(ptr: *mut T) -> Self {
+                Self(loom::sync::atomic::AtomicPtr::new(ptr))
+            }
+
+            pub(crate) fn load(&self, order: Ordering) -> *mut T {
+                self.0.load(order)
+            }
+
+            pub(crate) fn store(&self, ptr: *mut T, order: Ordering) {
+                self.0.store(ptr, order)
+            }
+
+            pub(crate) fn swap(&self, ptr: *mut T, order: Ordering) -> *mut T {
+                self.0.swap(ptr, order)
+            }
+
+            pub(crate) fn compare_exchange(
+                &self,
+                current: *mut T,
+                new: *mut T,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<*mut T, *mut T> {
+                self.0.compare_exchange(current, new, success, failure)
+            }
+
+            pub(crate) fn compare_exchange_weak(
+                &self,
+                current: *mut T,
+                new: *mut T,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<*mut T, *mut T> {
+                self.0.compare_exchange_weak(current, new, success, failure)
+            }
+
+            pub(crate) fn fetch_and(&self, mask: usize, order: Ordering) -> *mut T {
+                self.fetch_update_addr(order, |addr| addr & mask)
+            }
+
+            pub(crate) fn fetch_or(&self, mask: usize, order: Ordering) -> *mut T {
+                self.fetch_update_addr(order, |addr| addr | mask)
+            }
+
+            /// Emulates an atomic operation on the address with a compare-and-swap loop.
+            fn fetch_update_addr(&self, order: Ordering, f: impl Fn(usize) -> usize) -> *mut T {
+                let failure = match order {
+                    Ordering::Release => Ordering::Relaxed,
+                    Ordering::AcqRel => Ordering::Acquire,
+                    order => order,
+                };
+                let mut current = self.0.load(failure);
+                loop {
+                    match self
+                        .0
+                        .compare_exchange(current, current.map_addr(&f), order, failure)
+                    {
+                        Ok(previous) => break previous,
+                        Err(actual) => current = actual,
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Helpers shared by the unit tests.
+
+#[cfg(feature = "waker")]
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread,
+};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+#[cfg(feature = "waker")]
+pub(crate) use common::CountingWaker;
+#[cfg(feature = "alloc")]
+pub(crate) use common::spawn_popper;
+
+/// A waker that unparks a thread.
+#[cfg(feature = "waker")]
+struct ThreadWaker(thread::Thread);
+
+#[cfg(feature = "waker")]
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Polls a future on the current thread until it completes, parking while it
+/// is pending.
+#[cfg(feature = "waker")]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
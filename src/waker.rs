@@ -1,59 +1,451 @@
 use core::task::Waker;
 
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
 use alloc::{sync::Arc, task::Wake};
+#[cfg(feature = "std")]
+use core::any::Any;
+#[cfg(feature = "std")]
+use std::panic::{self, AssertUnwindSafe};
 
-use crate::Sack;
+use crate::{
+    Sack,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+        const_fn,
+    },
+};
+
+/// The state of a registration that hasn't been woken or cancelled yet.
+const REGISTERED: u8 = 0;
+/// The state of a registration that has been taken out of the set.
+const WOKEN: u8 = 1;
+/// The state of a registration that has been cancelled while still in the set.
+const CANCELLED: u8 = 2;
+
+/// The minimum number of cancelled registrations before they are cleaned up.
+const COMPACT_THRESHOLD: usize = 8;
+
+/// Wakes wakers that have been taken out of a set, making sure that a panicking
+/// waker doesn't keep the others from being woken.
+///
+/// With the `std` feature, panics are caught and the first one is raised again
+/// by [`Wakeups::finish`]. Without it, the remaining wakers of a batch are woken
+/// while unwinding, and a second panic aborts.
+#[derive(Default)]
+struct Wakeups {
+    /// The number of wakers that were woken.
+    count: usize,
+    /// The first panic of a waker.
+    #[cfg(feature = "std")]
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl Wakeups {
+    /// Wakes a batch of wakers.
+    fn wake(&mut self, wakers: impl Iterator<Item = Waker>) {
+        #[cfg(feature = "std")]
+        for waker in wakers {
+            self.count += 1;
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| waker.wake())) {
+                self.panic.get_or_insert(panic);
+            }
+        }
+
+        #[cfg(not(feature = "std"))]
+        {
+            /// Wakes the rest of the batch if a waker panics.
+            struct Rest<I: Iterator<Item = Waker>>(I);
+
+            impl<I: Iterator<Item = Waker>> Drop for Rest<I> {
+                fn drop(&mut self) {
+                    self.0.by_ref().for_each(Waker::wake);
+                }
+            }
+
+            let mut rest = Rest(wakers);
+            for waker in rest.0.by_ref() {
+                self.count += 1;
+                waker.wake();
+            }
+        }
+    }
+
+    /// Returns the number of wakers that were woken, raising the first panic
+    /// again if there was any.
+    fn finish(self) -> usize {
+        #[cfg(feature = "std")]
+        if let Some(panic) = self.panic {
+            // Panicking again while unwinding would abort.
+            if !std::thread::panicking() {
+                panic::resume_unwind(panic);
+            }
+        }
+        self.count
+    }
+}
+
+/// A waker added with [`WakerSet::register`].
+struct Registration {
+    waker: Waker,
+    state: Arc<AtomicU8>,
+}
+
+impl Registration {
+    /// Takes the waker out of the set, unless the registration has been cancelled.
+    fn take(self, cancelled: &AtomicUsize) -> Option<Waker> {
+        if self.state.swap(WOKEN, Ordering::AcqRel) == CANCELLED {
+            cancelled.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(self.waker)
+    }
+}
+
+/// What a [`WakerSet`] does with the wakers that are left when it is dropped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Wake them, so that no task is left waiting for a set that is gone.
+    #[default]
+    Wake,
+    /// Drop them without waking them, e.g. because their tasks have already been
+    /// cancelled.
+    Discard,
+    /// Drop them without waking them, but panic in debug builds if there are any.
+    ///
+    /// Cancelled registrations (see [`WakerSet::register`]) don't count.
+    AssertEmpty,
+}
 
 /// A set of wakers that can be woken all at once.
 ///
 /// This is useful for implementing synchronization primitives that need to wake up multiple tasks.
-#[derive(Default)]
-pub struct WakerSet(Sack<Waker>);
+///
+/// Entries are recycled between wakeups (see [`Sack::drain_recycle`]), so a set that
+/// is repeatedly filled and woken doesn't allocate once it has reached its usual size.
+///
+/// Wakers that are left when the set is dropped are woken, unless another
+/// [`DropPolicy`] is picked with [`WakerSet::with_drop_policy`].
+pub struct WakerSet {
+    wakers: Sack<Waker>,
+    /// Wakers added with [`WakerSet::register`], kept apart so that [`WakerSet::add`]
+    /// doesn't pay for cancellation.
+    registered: Sack<Registration>,
+    /// The number of cancelled registrations that are still in the set.
+    cancelled: AtomicUsize,
+    /// Incremented by every [`WakerSet::wake_all`] before it takes the wakers out.
+    generation: AtomicUsize,
+    /// The number of wakeups requested from [`WakerSet::wake_n`] that haven't been
+    /// handled yet.
+    pending: AtomicUsize,
+    /// Whether a thread is currently handling the pending wakeups.
+    waking: AtomicBool,
+    drop_policy: DropPolicy,
+}
+
+impl Default for WakerSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl WakerSet {
-    /// Creates a new, empty `WakerSet`.
-    pub const fn new() -> Self {
-        Self(Sack::new())
+    const_fn! {
+        /// Creates a new, empty `WakerSet`.
+        ///
+        /// The remaining wakers are woken when it is dropped.
+        pub const fn new() -> Self {
+            Self::with_drop_policy(DropPolicy::Wake)
+        }
+    }
+
+    const_fn! {
+        /// Creates a new, empty `WakerSet` that handles the remaining wakers as
+        /// `drop_policy` says when it is dropped.
+        ///
+        /// ## Example
+        ///
+        /// ```
+        /// use sack::{DropPolicy, WakerSet};
+        /// use std::task::Waker;
+        ///
+        /// let wake_set = WakerSet::with_drop_policy(DropPolicy::AssertEmpty);
+        /// let key = wake_set.register(Waker::noop());
+        /// drop(key);
+        /// // The cancelled registration doesn't count.
+        /// drop(wake_set);
+        /// ```
+        pub const fn with_drop_policy(drop_policy: DropPolicy) -> Self {
+            Self {
+                wakers: Sack::new(),
+                registered: Sack::with_len_tracking(),
+                cancelled: AtomicUsize::new(0),
+                generation: AtomicUsize::new(0),
+                pending: AtomicUsize::new(0),
+                waking: AtomicBool::new(false),
+                drop_policy,
+            }
+        }
     }
 
     /// Adds a waker to the set.
     pub fn add(&self, waker: Waker) {
-        self.0.add(waker);
+        self.wakers.add(waker);
     }
 
     /// Adds a waker to the set by reference.
     pub fn add_by_ref(&self, waker: &Waker) {
-        self.0.add(waker.clone());
+        self.wakers.add(waker.clone());
+    }
+
+    /// Adds a waker to the set by reference, unless it is already in it.
+    ///
+    /// A waker is considered to be in the set if [`Waker::will_wake`] returns
+    /// `true` for a waker that was added before with [`WakerSet::add`],
+    /// [`WakerSet::add_by_ref`] or this method. This keeps a task that is polled
+    /// repeatedly before being woken from filling up the set, at the cost of
+    /// walking the whole set on every call. Two threads adding the same waker at
+    /// the same time may still both add it.
+    ///
+    /// Returns `true` if the waker was added.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::WakerSet;
+    /// use std::task::Waker;
+    ///
+    /// let wake_set = WakerSet::new();
+    /// assert!(wake_set.add_unique(Waker::noop()));
+    /// assert!(!wake_set.add_unique(Waker::noop()));
+    /// assert_eq!(wake_set.wake_all(), 1);
+    /// ```
+    pub fn add_unique(&self, waker: &Waker) -> bool {
+        // SAFETY: `will_wake` only compares the wakers, it doesn't call into them.
+        if unsafe { self.wakers.any(|other| other.will_wake(waker)) } {
+            return false;
+        }
+        self.add_by_ref(waker);
+        true
+    }
+
+    /// Adds a waker to the set and returns a key that can cancel it again.
+    ///
+    /// Use this for futures that might be dropped before they are woken: dropping
+    /// the key (or calling [`WakerKey::cancel`]) makes sure the waker isn't woken,
+    /// and once enough registrations have been cancelled they are cleaned up, so
+    /// the set doesn't keep growing if it is rarely woken.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::WakerSet;
+    /// use std::task::Waker;
+    ///
+    /// let wake_set = WakerSet::new();
+    ///
+    /// let key = wake_set.register(Waker::noop());
+    /// drop(key);
+    /// assert_eq!(wake_set.wake_all(), 0);
+    ///
+    /// let key = wake_set.register(Waker::noop());
+    /// assert_eq!(wake_set.wake_all(), 1);
+    /// assert!(key.is_woken());
+    /// ```
+    pub fn register(&self, waker: &Waker) -> WakerKey<'_> {
+        let state = Arc::new(AtomicU8::new(REGISTERED));
+        self.registered.add(Registration {
+            waker: waker.clone(),
+            state: Arc::clone(&state),
+        });
+        WakerKey { set: self, state }
     }
 
     /// Wakes all wakers in the set.
     ///
     /// Returns the number of wakers that were woken.
+    ///
+    /// If a waker panics, the remaining ones are still woken. With the `std`
+    /// feature, the first panic is then raised again. Without it, the remaining
+    /// wakers are woken while unwinding, so a second panic aborts.
     pub fn wake_all(&self) -> usize {
-        let mut count = 0;
-        for waker in self.0.drain() {
-            waker.wake();
-            count += 1;
+        let mut wakeups = Wakeups::default();
+        self.wake_all_into(&mut wakeups);
+        wakeups.finish()
+    }
+
+    /// Wakes all wakers in the set.
+    fn wake_all_into(&self, wakeups: &mut Wakeups) {
+        // Lets `wake_oldest` know that wakers it had taken out may have been missed.
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        wakeups.wake(self.wakers.drain_recycle().chain(self.take_registered()));
+    }
+
+    /// Takes out all wakers added with [`WakerSet::register`] whose registration
+    /// hasn't been cancelled.
+    fn take_registered(&self) -> impl Iterator<Item = Waker> + '_ {
+        (!self.registered.is_empty())
+            .then(|| self.registered.drain_recycle())
+            .into_iter()
+            .flatten()
+            .filter_map(|registration| registration.take(&self.cancelled))
+    }
+
+    /// Wakes the waker that was added first, if there is any.
+    ///
+    /// Returns `true` if a waker was woken. See [`WakerSet::wake_n`] for details.
+    pub fn wake_one(&self) -> bool {
+        self.wake_n(1) != 0
+    }
+
+    /// Wakes up to `n` wakers in the order they were added, leaving the rest in
+    /// the set.
+    ///
+    /// Wakers added with [`WakerSet::add`] or [`WakerSet::add_by_ref`] come before
+    /// those added with [`WakerSet::register`]. This wakes only as many tasks as
+    /// can make progress, e.g. when a semaphore releases a single permit.
+    ///
+    /// Only one thread takes wakers out at a time. If another thread is already
+    /// doing so, it takes over the request and this returns right away, so the
+    /// returned number of woken wakers includes the ones woken for concurrent
+    /// callers, and may be zero even though wakers were woken.
+    ///
+    /// Panicking wakers are handled like in [`WakerSet::wake_all`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use sack::WakerSet;
+    /// use std::task::Waker;
+    ///
+    /// let wake_set = WakerSet::new();
+    /// for _ in 0..3 {
+    ///     wake_set.add_by_ref(Waker::noop());
+    /// }
+    ///
+    /// assert_eq!(wake_set.wake_n(2), 2);
+    /// assert!(wake_set.wake_one());
+    /// assert!(!wake_set.wake_one());
+    /// ```
+    pub fn wake_n(&self, n: usize) -> usize {
+        if n == 0 {
+            return 0;
+        }
+        let _ = self
+            .pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                Some(pending.saturating_add(n))
+            });
+
+        /// Lets other threads take wakers out again, even if a waker panics.
+        struct Waking<'a>(&'a AtomicBool);
+
+        impl Drop for Waking<'_> {
+            fn drop(&mut self) {
+                self.0.store(false, Ordering::SeqCst);
+            }
+        }
+
+        let mut wakeups = Wakeups::default();
+        while self.pending.load(Ordering::SeqCst) != 0
+            && self
+                .waking
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok()
+        {
+            let _waking = Waking(&self.waking);
+            loop {
+                let n = self.pending.swap(0, Ordering::SeqCst);
+                if n == 0 {
+                    break;
+                }
+                self.wake_oldest(n, &mut wakeups);
+            }
+            // Requests made after the last swap may have been left to us.
+        }
+        wakeups.finish()
+    }
+
+    /// Wakes up to `n` wakers in the order they were added.
+    fn wake_oldest(&self, n: usize, wakeups: &mut Wakeups) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let start = wakeups.count;
+        wakeups.wake(self.wakers.drain_oldest(n));
+        while wakeups.count - start < n {
+            let registrations = self.registered.drain_oldest(n - (wakeups.count - start));
+            if registrations.is_empty() {
+                break;
+            }
+            wakeups
+                .wake(registrations.filter_map(|registration| registration.take(&self.cancelled)));
+        }
+
+        // The wakers that weren't woken are put back after being taken out with
+        // the rest, so a concurrent `wake_all` might have missed them. Putting
+        // them back synchronizes with it, so its generation is visible here.
+        if self.generation.load(Ordering::Relaxed) != generation {
+            self.wake_all_into(wakeups);
         }
-        count
     }
 
     /// Clears all wakers from the set without waking them.
     ///
     /// Returns the number of wakers that were cleared.
     pub fn clear(&self) -> usize {
-        self.0.drain().count()
+        let registered = self
+            .registered
+            .drain_recycle()
+            .filter_map(|registration| registration.take(&self.cancelled))
+            .count();
+        self.wakers.drain_recycle().count() + registered
     }
 
     /// Checks if the set is empty.
+    ///
+    /// Cancelled registrations that haven't been cleaned up yet still count.
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.wakers.is_empty() && self.registered.is_empty()
+    }
+
+    /// Cleans up cancelled registrations if they make up most of the registrations.
+    fn compact(&self) {
+        let cancelled = self.cancelled.load(Ordering::Relaxed);
+        let len = self.registered.len().unwrap_or(0);
+        if cancelled >= COMPACT_THRESHOLD && cancelled * 2 > len {
+            let generation = self.generation.load(Ordering::Relaxed);
+            self.registered
+                .drain_filter(|registration| {
+                    registration.state.load(Ordering::Acquire) == CANCELLED
+                })
+                .for_each(|registration| {
+                    registration.take(&self.cancelled);
+                });
+
+            // The live registrations are put back, so a concurrent `wake_all` might
+            // have missed them (see `WakerSet::wake_oldest`).
+            if self.generation.load(Ordering::Relaxed) != generation {
+                let mut wakeups = Wakeups::default();
+                self.wake_all_into(&mut wakeups);
+                wakeups.finish();
+            }
+        }
     }
 }
 
 impl Drop for WakerSet {
     fn drop(&mut self) {
-        self.wake_all();
+        match self.drop_policy {
+            DropPolicy::Wake => {
+                self.wake_all();
+            }
+            DropPolicy::Discard => {
+                self.clear();
+            }
+            DropPolicy::AssertEmpty => {
+                let remaining = self.clear();
+                debug_assert_eq!(remaining, 0, "`WakerSet` dropped with wakers left");
+            }
+        }
     }
 }
 
@@ -66,3 +458,293 @@ impl Wake for WakerSet {
         self.wake_all();
     }
 }
+
+/// A registration in a [`WakerSet`].
+///
+/// This struct is created by [`WakerSet::register`]. See its documentation for more.
+/// The registration is cancelled when the key is dropped.
+pub struct WakerKey<'a> {
+    set: &'a WakerSet,
+    state: Arc<AtomicU8>,
+}
+
+impl WakerKey<'_> {
+    /// Checks if the waker has been taken out of the set, i.e. woken or cleared.
+    pub fn is_woken(&self) -> bool {
+        self.state.load(Ordering::Acquire) == WOKEN
+    }
+
+    /// Cancels the registration, so that the waker won't be woken.
+    ///
+    /// Returns `false` if the waker has already been taken out of the set.
+    pub fn cancel(self) -> bool {
+        self.try_cancel()
+    }
+
+    /// Cancels the registration if it is still in the set.
+    fn try_cancel(&self) -> bool {
+        if self.state.load(Ordering::Acquire) != REGISTERED {
+            return false;
+        }
+        // Counted first, so that `Slot::take` never uncounts it before.
+        self.set.cancelled.fetch_add(1, Ordering::Relaxed);
+        if self
+            .state
+            .compare_exchange(REGISTERED, CANCELLED, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            self.set.cancelled.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        self.set.compact();
+        true
+    }
+}
+
+impl Drop for WakerKey<'_> {
+    fn drop(&mut self) {
+        self.try_cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        thread,
+        vec::Vec,
+    };
+
+    use super::*;
+    use crate::test_util::CountingWaker;
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri gives every use of a vtable its own address")]
+    fn test_waker_set_add_unique() {
+        let first = Waker::from(CountingWaker::new());
+        let second = Waker::from(CountingWaker::new());
+
+        let wake_set = WakerSet::new();
+        for _ in 0..3 {
+            wake_set.add_unique(&first);
+            wake_set.add_unique(&second);
+        }
+        assert!(!wake_set.add_unique(&first.clone()));
+        assert_eq!(wake_set.wake_all(), 2);
+        assert!(wake_set.add_unique(&first));
+        assert_eq!(wake_set.clear(), 1);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "Miri gives every use of a vtable its own address")]
+    fn test_waker_set_concurrent_add_unique() {
+        let wakers: Vec<_> = (0..8).map(|_| Waker::from(CountingWaker::new())).collect();
+        let wake_set = WakerSet::new();
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..1000 {
+                        wake_set.add_unique(&wakers[i % wakers.len()]);
+                        if i % 100 == 0 {
+                            wake_set.wake_all();
+                        }
+                    }
+                });
+            }
+        });
+        assert!(wake_set.clear() <= 4 * wakers.len());
+    }
+
+    #[test]
+    fn test_waker_set_wake_n() {
+        let counters: Vec<_> = (0..5).map(|_| CountingWaker::new()).collect();
+        let woken = || -> Vec<_> { counters.iter().map(|counter| counter.count()).collect() };
+
+        let wake_set = WakerSet::new();
+        assert!(!wake_set.wake_one());
+        let key = wake_set.register(&Waker::from(counters[4].clone()));
+        for counter in &counters[..4] {
+            wake_set.add(Waker::from(counter.clone()));
+        }
+
+        assert!(wake_set.wake_one());
+        assert_eq!(woken(), [1, 0, 0, 0, 0]);
+        assert_eq!(wake_set.wake_n(2), 2);
+        assert_eq!(woken(), [1, 1, 1, 0, 0]);
+        assert_eq!(wake_set.wake_n(5), 2);
+        assert_eq!(woken(), [1, 1, 1, 1, 1]);
+        assert!(key.is_woken());
+        assert!(wake_set.is_empty());
+    }
+
+    #[test]
+    fn test_waker_set_concurrent_wake_n() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+        let wake_set = WakerSet::new();
+        for _ in 0..4000 {
+            wake_set.add_by_ref(&waker);
+        }
+
+        // Every request is handled, even if another thread is taking wakers out.
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        wake_set.wake_one();
+                    }
+                });
+            }
+        });
+        assert!(wake_set.is_empty());
+        assert_eq!(counter.count(), 4000);
+
+        // Wakers that are taken out and put back aren't missed by `wake_all`.
+        for _ in 0..4000 {
+            wake_set.add_by_ref(&waker);
+        }
+        thread::scope(|s| {
+            for i in 0..4 {
+                let wake_set = &wake_set;
+                s.spawn(move || {
+                    for j in 0..100 {
+                        if i == 0 && j == 50 {
+                            wake_set.wake_all();
+                        } else {
+                            wake_set.wake_one();
+                        }
+                    }
+                });
+            }
+        });
+
+        // No wakeup was lost, and no waker was woken twice.
+        assert!(wake_set.is_empty());
+        assert_eq!(counter.count(), 8000);
+    }
+
+    struct PanickingWaker(&'static str);
+
+    impl Wake for PanickingWaker {
+        fn wake(self: Arc<Self>) {
+            panic!("{}", self.0);
+        }
+    }
+
+    #[test]
+    fn test_waker_set_wake_all_panic() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+
+        let wake_set = WakerSet::new();
+        wake_set.add_by_ref(&waker);
+        wake_set.add(Waker::from(Arc::new(PanickingWaker("first"))));
+        wake_set.add_by_ref(&waker);
+        let key = wake_set.register(&waker);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| wake_set.wake_all()));
+        assert!(result.is_err());
+        assert_eq!(counter.count(), 3);
+        assert!(key.is_woken());
+        assert!(wake_set.is_empty());
+
+        // Taking wakers out one by one still works afterwards.
+        wake_set.add(Waker::from(Arc::new(PanickingWaker("first"))));
+        wake_set.add_by_ref(&waker);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| wake_set.wake_one()));
+        assert!(result.is_err());
+        assert!(wake_set.wake_one());
+        assert_eq!(counter.count(), 4);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_waker_set_wake_all_panics() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+
+        let wake_set = WakerSet::new();
+        wake_set.add_by_ref(&waker);
+        wake_set.add(Waker::from(Arc::new(PanickingWaker("second"))));
+        wake_set.add_by_ref(&waker);
+        wake_set.add(Waker::from(Arc::new(PanickingWaker("first"))));
+
+        // Every waker is woken, and the first panic is raised again.
+        let panic = panic::catch_unwind(AssertUnwindSafe(|| wake_set.wake_all())).unwrap_err();
+        assert_eq!(
+            panic.downcast_ref::<std::string::String>().unwrap(),
+            "first"
+        );
+        assert_eq!(counter.count(), 2);
+    }
+
+    #[test]
+    fn test_waker_set_drop_policy() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+
+        let wake_set = WakerSet::new();
+        wake_set.add_by_ref(&waker);
+        drop(wake_set);
+        assert_eq!(counter.count(), 1);
+
+        let wake_set = WakerSet::with_drop_policy(DropPolicy::Discard);
+        wake_set.add_by_ref(&waker);
+        drop(wake_set);
+        assert_eq!(counter.count(), 1);
+
+        let wake_set = WakerSet::with_drop_policy(DropPolicy::AssertEmpty);
+        drop(wake_set.register(&waker));
+        drop(wake_set);
+        assert_eq!(counter.count(), 1);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic = "dropped with wakers left"]
+    fn test_waker_set_drop_policy_assert_empty() {
+        let wake_set = WakerSet::with_drop_policy(DropPolicy::AssertEmpty);
+        wake_set.add_by_ref(Waker::noop());
+    }
+
+    #[test]
+    fn test_waker_set_register() {
+        let waker = CountingWaker::new();
+        let waker = Waker::from(waker);
+
+        let wake_set = WakerSet::new();
+        let cancelled = wake_set.register(&waker);
+        let woken = wake_set.register(&waker);
+        wake_set.add_by_ref(&waker);
+        assert!(cancelled.cancel());
+
+        assert_eq!(wake_set.wake_all(), 2);
+        assert!(woken.is_woken());
+        assert!(!woken.cancel());
+
+        let key = wake_set.register(&waker);
+        assert_eq!(wake_set.clear(), 1);
+        assert!(key.is_woken());
+    }
+
+    #[test]
+    fn test_waker_set_register_bounded() {
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+
+        let wake_set = WakerSet::new();
+        let live = wake_set.register(&waker);
+        for _ in 0..1000 {
+            drop(wake_set.register(&waker));
+            assert!(wake_set.registered.len().unwrap() <= 2 * COMPACT_THRESHOLD);
+        }
+
+        // Cleaning up only removes the cancelled registrations.
+        assert!(!live.is_woken());
+        assert_eq!(counter.count(), 0);
+        assert_eq!(wake_set.wake_all(), 1);
+        assert!(live.is_woken());
+        assert_eq!(counter.count(), 1);
+    }
+}
@@ -0,0 +1,56 @@
+//! Helpers shared by all tests.
+//!
+//! The unit tests include this file from `src/test_util.rs`, so it only uses
+//! `std` and doesn't name the crate.
+#![allow(dead_code)]
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    task::Wake,
+    thread,
+    vec::Vec,
+};
+
+/// A waker that counts how often it was woken.
+pub struct CountingWaker {
+    count: AtomicUsize,
+}
+
+impl CountingWaker {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            count: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+}
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Spawns a thread that keeps popping items with `pop` until `done` is set.
+///
+/// Popping registers the thread as a reader of the sack, so some of the chains
+/// that are detached in the meantime are shared.
+pub fn spawn_popper<'scope, T: Send + 'scope>(
+    s: &'scope thread::Scope<'scope, '_>,
+    pop: impl Fn() -> Option<T> + Send + 'scope,
+    done: &'scope AtomicBool,
+) -> thread::ScopedJoinHandle<'scope, Vec<T>> {
+    s.spawn(move || {
+        let mut popped = Vec::new();
+        while !done.load(Ordering::Relaxed) {
+            popped.extend(pop());
+        }
+        popped
+    })
+}
@@ -0,0 +1,173 @@
+//! Model checks for the lock-free paths.
+//!
+//! These explore every interleaving of a few operations, so they only run when
+//! the crate is built with loom's atomics:
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --test loom --release
+//! ```
+#![cfg(loom)]
+
+use std::task::{Context, Poll, Waker};
+
+use common::CountingWaker;
+use loom::{sync::Arc, thread};
+use sack::{AsyncSack, Sack, WakerSet};
+
+mod common;
+
+#[test]
+fn add_drain() {
+    loom::model(|| {
+        let sack = Arc::new(Sack::new());
+        let producers: Vec<_> = (0..2)
+            .map(|i| {
+                let sack = sack.clone();
+                thread::spawn(move || sack.add(i))
+            })
+            .collect();
+
+        let mut drained: Vec<_> = sack.drain().collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        drained.extend(sack.drain());
+
+        drained.sort();
+        assert_eq!(drained, [0, 1]);
+        assert!(sack.is_empty());
+    });
+}
+
+#[test]
+fn add_is_empty() {
+    loom::model(|| {
+        let sack = Arc::new(Sack::new());
+        let producer = {
+            let sack = sack.clone();
+            thread::spawn(move || sack.add(0))
+        };
+
+        let drained = sack.drain().count();
+        // Whatever the drain missed is still visible once the add is done.
+        producer.join().unwrap();
+        assert_eq!(sack.is_empty(), drained == 1);
+    });
+}
+
+#[test]
+fn add_all_drain_fifo() {
+    loom::model(|| {
+        let sack = Arc::new(Sack::new());
+        let producer = {
+            let sack = sack.clone();
+            thread::spawn(move || sack.add_all(0..2))
+        };
+
+        let mut drained: Vec<_> = sack.drain_fifo().collect();
+        producer.join().unwrap();
+        drained.extend(sack.drain_fifo());
+
+        assert_eq!(drained, [0, 1]);
+    });
+}
+
+#[test]
+fn waker_set_add_wake_all() {
+    loom::model(|| {
+        let wake_set = Arc::new(WakerSet::new());
+        let wakers: Vec<_> = (0..2).map(|_| CountingWaker::new()).collect();
+
+        let adders: Vec<_> = wakers
+            .iter()
+            .map(|waker| {
+                let wake_set = wake_set.clone();
+                let waker = Waker::from(waker.clone());
+                thread::spawn(move || wake_set.add(waker))
+            })
+            .collect();
+
+        let mut woken = wake_set.wake_all();
+        for adder in adders {
+            adder.join().unwrap();
+        }
+        woken += wake_set.wake_all();
+
+        assert_eq!(woken, 2);
+        for waker in &wakers {
+            assert_eq!(waker.count(), 1);
+        }
+    });
+}
+
+#[test]
+fn add_pop_drain() {
+    loom::model(|| {
+        let sack = Arc::new(Sack::new());
+        sack.add(0);
+        let popper = {
+            let sack = sack.clone();
+            thread::spawn(move || sack.pop())
+        };
+
+        sack.add(1);
+        let mut items: Vec<_> = sack.drain().collect();
+        items.extend(popper.join().unwrap());
+        items.extend(sack.drain());
+
+        // Each item is taken exactly once, by either side.
+        items.sort();
+        assert_eq!(items, [0, 1]);
+    });
+}
+
+#[test]
+fn pop_drain_fifo() {
+    loom::model(|| {
+        let sack = Arc::new(Sack::new());
+        sack.add_all(0..2);
+        let popper = {
+            let sack = sack.clone();
+            thread::spawn(move || sack.pop())
+        };
+
+        let drained: Vec<_> = sack.drain_fifo().collect();
+        let popped = popper.join().unwrap();
+        let rest: Vec<_> = sack.drain_fifo().collect();
+
+        // Either the pop takes the newest item first, or the drain takes both.
+        match popped {
+            Some(1) => assert_eq!(drained, [0]),
+            None => assert_eq!(drained, [0, 1]),
+            Some(item) => panic!("popped {item} out of order"),
+        }
+        assert!(rest.is_empty());
+    });
+}
+
+#[test]
+fn async_sack_add_poll_drain() {
+    loom::model(|| {
+        let sack = Arc::new(AsyncSack::new());
+        let counter = CountingWaker::new();
+        let waker = Waker::from(counter.clone());
+
+        let producer = {
+            let sack = sack.clone();
+            thread::spawn(move || sack.add(0))
+        };
+
+        let mut cx = Context::from_waker(&waker);
+        let polled = sack.poll_drain(&mut cx).map(|batch| batch.count());
+        producer.join().unwrap();
+
+        // A consumer that missed the item must have been woken for it.
+        match polled {
+            Poll::Ready(count) => assert_eq!(count, 1),
+            Poll::Pending => {
+                assert_eq!(counter.count(), 1);
+                assert_eq!(sack.drain().count(), 1);
+            }
+        }
+    });
+}
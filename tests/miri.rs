@@ -0,0 +1,505 @@
+//! Checks for the unsafe code, sized to run under Miri:
+//!
+//! ```sh
+//! cargo +nightly miri test --all-features --test miri
+//! ```
+//!
+//! The items own heap memory and count their drops, so Miri reports any entry
+//! that is freed twice, read after being freed or leaked, and the assertions
+//! catch items that are dropped twice or never. The iteration counts are kept
+//! small so that the whole suite stays fast in the interpreter.
+#![cfg(feature = "waker")]
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread,
+};
+
+use common::{CountingWaker, spawn_popper};
+use sack::{DropPolicy, Event, IntrusiveSack, Link, Linked, Sack, WakerSet, channel};
+
+mod common;
+
+const THREADS: usize = 3;
+const ITEMS: usize = if cfg!(miri) { 8 } else { 100 };
+
+/// An item that owns an allocation and counts how often it was dropped.
+struct Tracked<'a> {
+    value: Box<usize>,
+    drops: &'a AtomicUsize,
+}
+
+impl<'a> Tracked<'a> {
+    fn new(value: usize, drops: &'a AtomicUsize) -> Self {
+        Self {
+            value: Box::new(value),
+            drops,
+        }
+    }
+}
+
+impl Drop for Tracked<'_> {
+    fn drop(&mut self) {
+        self.drops.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A waker that adds another waker to the set it is woken from.
+struct ReentrantWaker {
+    wake_set: Arc<WakerSet>,
+    inner: Waker,
+}
+
+impl Wake for ReentrantWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_set.add_by_ref(&self.inner);
+    }
+}
+
+#[test]
+fn sack_drop_paths() {
+    let drops = AtomicUsize::new(0);
+    let sack = Sack::with_len_tracking();
+    for i in 0..ITEMS {
+        sack.add(Tracked::new(i, &drops));
+    }
+    sack.add_all((0..ITEMS).map(|i| Tracked::new(i, &drops)));
+
+    // Partially consumed drains drop the rest.
+    let mut drain = sack.drain_oldest(ITEMS / 2);
+    assert_eq!(*drain.next().unwrap().value, 0);
+    drop(drain);
+    assert_eq!(drops.load(Ordering::Relaxed), ITEMS / 2);
+
+    let popped = sack.pop().unwrap();
+    assert_eq!(*popped.value, ITEMS - 1);
+    drop(popped);
+
+    let mut recycle = sack.drain_recycle();
+    recycle.next();
+    drop(recycle);
+    assert_eq!(drops.load(Ordering::Relaxed), 2 * ITEMS);
+    assert_eq!(sack.len(), Some(0));
+
+    // Reused entries hold items again.
+    sack.add_all((0..ITEMS).map(|i| Tracked::new(i, &drops)));
+    let values: Vec<_> = sack.drain_fifo().map(|item| *item.value).collect();
+    assert_eq!(values, (0..ITEMS).collect::<Vec<_>>());
+
+    // Items left behind are dropped with the sack, and rejected ones right away.
+    sack.add(Tracked::new(0, &drops));
+    assert!(sack.close());
+    let rejected = sack.try_add(Tracked::new(1, &drops)).unwrap_err();
+    drop(rejected);
+    sack.add_all((0..ITEMS).map(|i| Tracked::new(i, &drops)));
+    drop(sack);
+    assert_eq!(drops.load(Ordering::Relaxed), 4 * ITEMS + 2);
+}
+
+#[test]
+fn sack_bounded() {
+    let drops = AtomicUsize::new(0);
+    let sack = Sack::bounded(2);
+    sack.add(Tracked::new(0, &drops));
+    sack.add_all((1..3).map(|i| Tracked::new(i, &drops)));
+    assert!(sack.try_add(Tracked::new(3, &drops)).is_ok());
+    assert!(sack.try_add(Tracked::new(4, &drops)).is_err());
+    assert_eq!(drops.load(Ordering::Relaxed), 3);
+    let mut rejected = sack
+        .try_add_all((5..7).map(|i| Tracked::new(i, &drops)))
+        .unwrap_err();
+    assert_eq!(rejected.next().map(|item| *item.value), Some(5));
+    drop(rejected);
+    assert_eq!(drops.load(Ordering::Relaxed), 5);
+    let mut drain = sack.close_and_drain();
+    drain.next();
+    assert!(sack.try_add(Tracked::new(7, &drops)).is_err());
+    drop(drain);
+    assert_eq!(drops.load(Ordering::Relaxed), 8);
+}
+
+#[test]
+fn sack_concurrent() {
+    let drops = AtomicUsize::new(0);
+    let done = AtomicBool::new(false);
+    let sack = Sack::new();
+    let mut seen = thread::scope(|s| {
+        for t in 0..THREADS {
+            let sack = &sack;
+            let drops = &drops;
+            s.spawn(move || {
+                for i in 0..ITEMS {
+                    sack.add(Tracked::new(t * ITEMS + i, drops));
+                }
+            });
+        }
+
+        let popper = spawn_popper(s, || sack.pop(), &done);
+        let mut seen = Vec::new();
+        for round in 0..ITEMS {
+            let values = match round % 3 {
+                0 => sack.drain().map(|item| *item.value).collect::<Vec<_>>(),
+                1 => sack.drain_fifo().map(|item| *item.value).collect(),
+                _ => sack.drain_oldest(2).map(|item| *item.value).collect(),
+            };
+            seen.extend(values);
+        }
+        done.store(true, Ordering::Relaxed);
+        seen.extend(popper.join().unwrap().iter().map(|item| *item.value));
+        seen
+    });
+    seen.extend(sack.drain_recycle().map(|item| *item.value));
+
+    seen.sort();
+    assert_eq!(seen, (0..THREADS * ITEMS).collect::<Vec<_>>());
+    assert_eq!(drops.load(Ordering::Relaxed), THREADS * ITEMS);
+}
+
+#[test]
+fn sack_concurrent_pop() {
+    let drops = AtomicUsize::new(0);
+    let sack = Sack::with_len_tracking();
+    // Every thread pops, so entries are retired and collected while others are
+    // still retiring their own.
+    let popped: usize = thread::scope(|s| {
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let sack = &sack;
+                let drops = &drops;
+                s.spawn(move || {
+                    let mut popped = 0;
+                    for i in 0..ITEMS {
+                        sack.add(Tracked::new(t * ITEMS + i, drops));
+                        popped += sack.pop().into_iter().count();
+                    }
+                    popped
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).sum()
+    });
+
+    assert_eq!(popped + sack.drain().count(), THREADS * ITEMS);
+    assert_eq!(drops.load(Ordering::Relaxed), THREADS * ITEMS);
+}
+
+#[test]
+fn sack_concurrent_drain_filter() {
+    let drops = AtomicUsize::new(0);
+    let done = AtomicBool::new(false);
+    let sack = Sack::with_len_tracking();
+    let mut taken = thread::scope(|s| {
+        for t in 0..THREADS {
+            let sack = &sack;
+            let drops = &drops;
+            s.spawn(move || {
+                for i in 0..ITEMS {
+                    sack.add(Tracked::new(t * ITEMS + i, drops));
+                }
+            });
+        }
+        let popper = spawn_popper(s, || sack.pop(), &done);
+        let mut taken = Vec::new();
+        for _ in 0..ITEMS {
+            taken.extend(
+                sack.drain_filter(|item| *item.value % 2 == 0)
+                    .map(|item| *item.value),
+            );
+        }
+        done.store(true, Ordering::Relaxed);
+        taken.extend(popper.join().unwrap().iter().map(|item| *item.value));
+        taken
+    });
+    taken.extend(sack.drain().map(|item| *item.value));
+
+    taken.sort();
+    assert_eq!(taken, (0..THREADS * ITEMS).collect::<Vec<_>>());
+    assert_eq!(sack.len(), Some(0));
+    assert_eq!(drops.load(Ordering::Relaxed), THREADS * ITEMS);
+}
+
+#[test]
+fn sack_concurrent_append() {
+    let drops = AtomicUsize::new(0);
+    let done = AtomicBool::new(false);
+    let global = Sack::with_len_tracking();
+    let mut seen = thread::scope(|s| {
+        for t in 0..THREADS {
+            let global = &global;
+            let drops = &drops;
+            s.spawn(move || {
+                let local = Sack::new();
+                for i in 0..ITEMS {
+                    local.add(Tracked::new(t * ITEMS + i, drops));
+                    if i % 2 == 1 {
+                        assert!(global.append_sack(&local).is_ok());
+                    }
+                }
+            });
+        }
+        let popper = spawn_popper(s, || global.pop(), &done);
+        let mut seen = Vec::new();
+        for _ in 0..ITEMS {
+            seen.extend(global.drain().map(|item| *item.value));
+        }
+        done.store(true, Ordering::Relaxed);
+        seen.extend(popper.join().unwrap().iter().map(|item| *item.value));
+        seen
+    });
+    seen.extend(global.drain().map(|item| *item.value));
+
+    seen.sort();
+    assert_eq!(seen, (0..THREADS * ITEMS).collect::<Vec<_>>());
+    assert_eq!(global.len(), Some(0));
+    assert_eq!(drops.load(Ordering::Relaxed), THREADS * ITEMS);
+}
+
+#[test]
+fn sack_concurrent_iter_shared() {
+    let sack = Sack::new();
+    let drained = thread::scope(|s| {
+        for t in 0..THREADS {
+            let sack = &sack;
+            s.spawn(move || {
+                for i in 0..ITEMS {
+                    sack.add(t * ITEMS + i);
+                }
+            });
+        }
+        // Drained entries are freed or copied while the readers look at them.
+        let drainer = s.spawn(|| {
+            (0..ITEMS)
+                .map(|_| sack.drain_oldest(2).count() + sack.drain_fifo().count())
+                .sum::<usize>()
+        });
+        for _ in 0..ITEMS {
+            assert!(sack.iter_shared().all(|item| item < THREADS * ITEMS));
+            sack.for_each_ref(|&item| assert!(item < THREADS * ITEMS));
+        }
+        drainer.join().unwrap()
+    });
+    assert_eq!(drained + sack.iter_shared().count(), THREADS * ITEMS);
+}
+
+#[test]
+fn sack_concurrent_recycle() {
+    let sack = Sack::new();
+    let total = thread::scope(|s| {
+        let producer = s.spawn(|| {
+            for i in 0..ITEMS {
+                sack.add(Box::new(i));
+            }
+        });
+        let mut total = 0;
+        for _ in 0..ITEMS {
+            total += sack.drain_recycle().map(|item| *item).sum::<usize>();
+        }
+        producer.join().unwrap();
+        total
+    });
+    let rest: usize = sack.drain_recycle().map(|item| *item).sum();
+    assert_eq!(total + rest, (0..ITEMS).sum());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn sack_concurrent_drain_blocking() {
+    use std::time::Duration;
+
+    let sack = Sack::<Box<usize>>::new();
+    let seen = thread::scope(|s| {
+        // The sleepers live on the stacks of the blocked threads, and the ones
+        // that time out take out the others as well.
+        let waiters: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let sack = &sack;
+                s.spawn(move || {
+                    let mut seen = Vec::new();
+                    while !sack.is_closed() || !sack.is_empty() {
+                        let batch = match t {
+                            0 => sack.drain_timeout(Duration::from_micros(10)),
+                            _ => sack.drain_blocking(),
+                        };
+                        seen.extend(batch.map(|item| *item));
+                    }
+                    seen
+                })
+            })
+            .collect();
+        for i in 0..ITEMS {
+            sack.add(Box::new(i));
+            thread::yield_now();
+        }
+        sack.close();
+        waiters
+            .into_iter()
+            .flat_map(|waiter| waiter.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    assert_eq!(seen.iter().sum::<usize>(), (0..ITEMS).sum());
+}
+
+#[test]
+fn intrusive_sack() {
+    struct Node {
+        value: Box<usize>,
+        link: Link,
+    }
+
+    impl Linked for Node {
+        fn link(&self) -> &Link {
+            &self.link
+        }
+    }
+
+    let nodes: Vec<_> = (0..ITEMS)
+        .map(|value| Node {
+            value: Box::new(value),
+            link: Link::new(),
+        })
+        .collect();
+    let sack = IntrusiveSack::new();
+    let mut seen = thread::scope(|s| {
+        for chunk in nodes.chunks(ITEMS / 2) {
+            let sack = &sack;
+            s.spawn(move || {
+                for node in chunk {
+                    assert!(sack.add(node));
+                }
+            });
+        }
+        let mut seen = Vec::new();
+        while seen.len() < ITEMS {
+            seen.extend(sack.drain().map(|node| *node.value));
+        }
+        seen
+    });
+
+    seen.sort();
+    assert_eq!(seen, (0..ITEMS).collect::<Vec<_>>());
+    assert!(sack.add(&nodes[0]));
+    drop(sack);
+    assert!(!nodes[0].link.is_linked());
+}
+
+#[test]
+fn waker_set_custom_wakers() {
+    let wake_set = Arc::new(WakerSet::new());
+    let counting = CountingWaker::new();
+    let waker = Waker::from(counting.clone());
+
+    wake_set.add_by_ref(&waker);
+    let key = wake_set.register(&waker);
+    let cancelled = wake_set.register(&waker);
+    assert!(cancelled.cancel());
+    assert_eq!(wake_set.wake_n(1), 1);
+    assert_eq!(wake_set.wake_all(), 1);
+    assert!(key.is_woken());
+    drop(key);
+    assert_eq!(counting.count(), 2);
+
+    // Wakers may add to the set they are woken from.
+    wake_set.add(Waker::from(Arc::new(ReentrantWaker {
+        wake_set: wake_set.clone(),
+        inner: waker.clone(),
+    })));
+    assert_eq!(wake_set.wake_all(), 1);
+    assert_eq!(wake_set.wake_all(), 1);
+    assert_eq!(counting.count(), 3);
+
+    // Waking the set itself wakes everything in it.
+    wake_set.add_by_ref(&waker);
+    Waker::from(wake_set.clone()).wake_by_ref();
+    assert_eq!(counting.count(), 4);
+
+    let discard = WakerSet::with_drop_policy(DropPolicy::Discard);
+    discard.add_by_ref(&waker);
+    drop(discard);
+    let wake = WakerSet::new();
+    wake.add(waker);
+    drop(wake);
+    assert_eq!(counting.count(), 5);
+}
+
+#[test]
+fn waker_set_concurrent() {
+    let wake_set = WakerSet::new();
+    let counting = CountingWaker::new();
+    let waker = Waker::from(counting.clone());
+
+    let woken = thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                let mut keys = Vec::new();
+                for i in 0..ITEMS {
+                    match i % 3 {
+                        0 => wake_set.add_by_ref(&waker),
+                        1 => keys.push(wake_set.register(&waker)),
+                        _ => {
+                            wake_set.register(&waker).cancel();
+                        }
+                    }
+                }
+                // Cancels the registrations that haven't been woken yet.
+                drop(keys);
+            });
+        }
+        let mut woken = 0;
+        for i in 0..ITEMS {
+            woken += if i % 2 == 0 {
+                wake_set.wake_all()
+            } else {
+                wake_set.wake_n(2)
+            };
+        }
+        woken
+    });
+    let woken = woken + wake_set.wake_all();
+    // Every plain waker is woken, and some of the registrations.
+    assert!(woken >= THREADS * ITEMS.div_ceil(3));
+}
+
+#[test]
+fn event_and_channel() {
+    let counting = CountingWaker::new();
+    let waker = Waker::from(counting.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    let event = Event::new();
+    let mut first = pin!(event.listen());
+    let second = event.listen();
+    assert!(first.as_mut().poll(&mut cx).is_pending());
+    assert!(event.notify_one());
+    assert_eq!(counting.count(), 1);
+    assert!(first.as_mut().poll(&mut cx).is_ready());
+    drop(second);
+
+    let drops = AtomicUsize::new(0);
+    let (sender, mut receiver) = channel::channel();
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let sender = sender.clone();
+            let drops = &drops;
+            s.spawn(move || {
+                for i in 0..ITEMS {
+                    assert!(sender.send(Tracked::new(t * ITEMS + i, drops)).is_ok());
+                }
+            });
+        }
+    });
+    drop(sender);
+
+    let mut received = 0;
+    while let Poll::Ready(Some(batch)) = receiver.poll_recv(&mut cx) {
+        received += batch.count();
+    }
+    assert_eq!(received, THREADS * ITEMS);
+    assert_eq!(drops.load(Ordering::Relaxed), THREADS * ITEMS);
+}